@@ -0,0 +1,237 @@
+//! Local REST API serving each sensor's latest values, for scripts that would rather poll than
+//! subscribe
+//!
+//! `GET /api/sensors` lists every sensor and `GET /api/sensors/{id}` returns one.
+
+use crate::config::Sensor;
+use crate::sink::{Record, Sink};
+use crate::timestamp;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// JSON of each sensor's latest reading, by ID
+type Latest = Arc<Mutex<BTreeMap<String, serde_json::Value>>>;
+
+/// Keeps the values the API serves up to date
+pub struct Api {
+    latest: Latest,
+}
+
+impl Api {
+    /// Listens on `addr`, serving requests from a background thread
+    pub fn start(addr: &str, sensors: &[Sensor]) -> Result<Self, Box<dyn Error>> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("API on {addr}: {e}"))?;
+        info!("API listening on {addr}");
+        let latest: Latest = Arc::new(Mutex::new(
+            sensors
+                .iter()
+                .map(|sensor| (sensor.id().to_string(), sensor_json(sensor.id(), None)))
+                .collect(),
+        ));
+        let shared = Arc::clone(&latest);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!("Error accepting API connection: {e}");
+                        continue;
+                    }
+                };
+                // each on its own thread so a slow client doesn't hold up the others
+                let latest = Arc::clone(&shared);
+                thread::spawn(move || {
+                    if let Err(e) = respond(stream, &latest) {
+                        warn!("Error serving API request: {e}");
+                    }
+                });
+            }
+        });
+        Ok(Self { latest })
+    }
+}
+
+impl Sink for Api {
+    fn write(&mut self, record: &Record) {
+        let json = sensor_json(&record.sensor_id, Some(record));
+        self.latest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(record.sensor_id.clone(), json);
+    }
+}
+
+/// A sensor's latest reading, or nulls before the first one
+fn sensor_json(sensor_id: &str, record: Option<&Record>) -> serde_json::Value {
+    let values: Option<serde_json::Map<_, _>> = record.map(|record| {
+        record
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), serde_json::json!(value)))
+            .collect()
+    });
+    serde_json::json!({
+        "sensor_id": sensor_id,
+        "timestamp": record.map(|record| timestamp::format(record.time)),
+        "values": values,
+    })
+}
+
+/// Answers one request on a connection and closes it
+fn respond(stream: TcpStream, latest: &Latest) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // headers don't matter, but are read so closing doesn't reset the connection
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (method, path) = request_line(&request);
+    let (status, body) = route(
+        method,
+        path,
+        &latest.lock().unwrap_or_else(PoisonError::into_inner),
+    );
+
+    let body = body.to_string();
+    write!(
+        &stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// Method and path of a request line like `GET /api/sensors?pretty HTTP/1.1`, without the query
+/// string or a trailing slash
+fn request_line(request: &str) -> (&str, &str) {
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('/');
+    (method, path)
+}
+
+/// Status and JSON body answering a request
+fn route(
+    method: &str,
+    path: &str,
+    latest: &BTreeMap<String, serde_json::Value>,
+) -> (&'static str, serde_json::Value) {
+    match (method, path) {
+        ("GET", "/api/sensors") => ("200 OK", latest.values().cloned().collect()),
+        ("GET", path) => match path
+            .strip_prefix("/api/sensors/")
+            .and_then(|id| latest.get(id))
+        {
+            Some(sensor) => ("200 OK", sensor.clone()),
+            None => ("404 Not Found", serde_json::json!({ "error": "not found" })),
+        },
+        _ => (
+            "405 Method Not Allowed",
+            serde_json::json!({ "error": "method not allowed" }),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::Read;
+    use std::time::{Instant, UNIX_EPOCH};
+
+    fn latest() -> BTreeMap<String, serde_json::Value> {
+        let record = Record {
+            sensor_id: String::from("attic"),
+            time: UNIX_EPOCH + Duration::from_secs(1_706_688_900),
+            values: vec![(String::from("dewpoint"), 48.6)],
+        };
+        BTreeMap::from([
+            (String::from("attic"), sensor_json("attic", Some(&record))),
+            (String::from("basement"), sensor_json("basement", None)),
+        ])
+    }
+
+    #[test]
+    fn request_lines() {
+        assert_eq!(
+            request_line("GET /api/sensors HTTP/1.1\r\n"),
+            ("GET", "/api/sensors")
+        );
+        assert_eq!(
+            request_line("GET /api/sensors/attic/?pretty=1 HTTP/1.1\r\n"),
+            ("GET", "/api/sensors/attic")
+        );
+        assert_eq!(request_line(""), ("", ""));
+    }
+
+    #[test]
+    fn routes() {
+        let latest = latest();
+        let attic = json!({
+            "sensor_id": "attic",
+            "timestamp": "2024-01-31T08:15:00Z",
+            "values": {"dewpoint": 48.6},
+        });
+        let basement = json!({"sensor_id": "basement", "timestamp": null, "values": null});
+
+        assert_eq!(
+            route("GET", "/api/sensors", &latest),
+            ("200 OK", json!([attic, basement]))
+        );
+        assert_eq!(
+            route("GET", "/api/sensors/attic", &latest),
+            ("200 OK", attic)
+        );
+        assert_eq!(
+            route("GET", "/api/sensors/garage", &latest).0,
+            "404 Not Found"
+        );
+        assert_eq!(route("GET", "/", &latest).0, "404 Not Found");
+        assert_eq!(
+            route("POST", "/api/sensors", &latest).0,
+            "405 Method Not Allowed"
+        );
+    }
+
+    #[test]
+    fn idle_client_doesnt_block_others() {
+        // a free port, which the API then listens on
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let _api = Api::start(&addr.to_string(), &[]).unwrap();
+
+        let _idle = TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /api/sensors HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        assert!(response.ends_with("\r\n\r\n[]"), "{response}");
+        assert!(started.elapsed() < TIMEOUT);
+    }
+}
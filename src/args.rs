@@ -0,0 +1,194 @@
+use crate::logging::LogFormat;
+use std::error::Error;
+
+/// What to do with the Windows service
+#[derive(Clone, Copy)]
+pub enum ServiceCommand {
+    /// Run as the service, when started by the service control manager
+    Run,
+    /// Register the service to run with the given config at startup
+    Install,
+    Uninstall,
+}
+
+/// Format of exported history
+#[derive(Clone, Copy, Default)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown export format {s}, expected csv or json")),
+        }
+    }
+}
+
+/// Which history to export and how
+#[derive(Default)]
+pub struct Export {
+    pub sensor: Option<String>,
+    /// Earliest timestamp to include, as ISO 8601 in UTC or a prefix of it like a date
+    pub from: Option<String>,
+    /// Timestamp (or prefix) before which to stop
+    pub to: Option<String>,
+    pub format: ExportFormat,
+}
+
+/// Something to do other than run the daemon
+pub enum Command {
+    /// Write a sensor's stored history to stdout
+    Export(Export),
+    /// Publish one message and exit
+    Publish {
+        topic: String,
+        payload: String,
+        retain: bool,
+    },
+    /// Print every message on a topic to stdout until interrupted
+    Subscribe { topic: String },
+    /// Feed a capture of messages to the sensors instead of reading their topics, `speed` times as
+    /// fast as it was recorded, printing what would be published unless `publish`
+    Replay {
+        file: String,
+        speed: f64,
+        publish: bool,
+    },
+}
+
+/// Command line arguments: an optional subcommand and config path followed by any overrides
+pub struct Args {
+    pub command: Option<Command>,
+    pub config: String,
+    pub broker: Option<String>,
+    pub username: Option<String>,
+    pub client_id: Option<String>,
+    pub discovery_prefix: Option<String>,
+    /// Delete every discovery config and exit
+    pub cleanup: bool,
+    pub log_format: LogFormat,
+    pub service: Option<ServiceCommand>,
+    /// Fork to the background
+    pub daemon: bool,
+    pub pid_file: Option<String>,
+    /// User to run as after forking
+    pub user: Option<String>,
+    /// File stdout and stderr are appended to after forking, instead of discarded
+    pub log_file: Option<String>,
+    /// Log every message published and received with a hex dump of its payload
+    pub trace_packets: bool,
+    /// Seconds between synthetic readings fed to sensors instead of reading their topics
+    pub simulate: Option<u64>,
+}
+
+impl Args {
+    pub fn parse() -> Result<Self, Box<dyn Error>> {
+        let mut args = std::env::args().skip(1).peekable();
+        let mut parsed = Self {
+            command: None,
+            config: String::from("config.toml"),
+            broker: None,
+            username: None,
+            client_id: None,
+            discovery_prefix: None,
+            cleanup: false,
+            log_format: LogFormat::default(),
+            service: None,
+            daemon: false,
+            pid_file: None,
+            user: None,
+            log_file: None,
+            trace_packets: false,
+            simulate: None,
+        };
+
+        let command =
+            args.next_if(|arg| ["export", "pub", "sub", "replay"].contains(&arg.as_str()));
+        let mut topic = || args.next().ok_or("Missing topic");
+        parsed.command = match command.as_deref() {
+            Some("export") => Some(Command::Export(Export::default())),
+            Some("pub") => Some(Command::Publish {
+                topic: topic()?,
+                payload: args.next().ok_or("Missing payload")?,
+                retain: false,
+            }),
+            Some("sub") => Some(Command::Subscribe { topic: topic()? }),
+            Some("replay") => Some(Command::Replay {
+                file: args.next().ok_or("Missing capture file")?,
+                speed: 1.0,
+                publish: false,
+            }),
+            _ => None,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {arg}"))
+            };
+            match arg.as_str() {
+                "--broker" => parsed.broker = Some(value()?),
+                "--username" => parsed.username = Some(value()?),
+                "--client-id" => parsed.client_id = Some(value()?),
+                "--discovery-prefix" => parsed.discovery_prefix = Some(value()?),
+                "--cleanup" => parsed.cleanup = true,
+                "--log-format" => parsed.log_format = value()?.parse()?,
+                "--service" => parsed.service = Some(ServiceCommand::Run),
+                "--install-service" => parsed.service = Some(ServiceCommand::Install),
+                "--uninstall-service" => parsed.service = Some(ServiceCommand::Uninstall),
+                "--daemon" => parsed.daemon = true,
+                "--pid-file" => parsed.pid_file = Some(value()?),
+                "--user" => parsed.user = Some(value()?),
+                "--log-file" => parsed.log_file = Some(value()?),
+                "--trace-packets" => parsed.trace_packets = true,
+                "--simulate" => match value()?.parse()? {
+                    0 => return Err("--simulate needs a positive number of seconds".into()),
+                    seconds => parsed.simulate = Some(seconds),
+                },
+                "--retain" => {
+                    let Some(Command::Publish { retain, .. }) = &mut parsed.command else {
+                        return Err("--retain is only valid with pub".into());
+                    };
+                    *retain = true;
+                }
+                "--publish" => {
+                    let Some(Command::Replay { publish, .. }) = &mut parsed.command else {
+                        return Err("--publish is only valid with replay".into());
+                    };
+                    *publish = true;
+                }
+                "--speed" => {
+                    let Some(Command::Replay { speed, .. }) = &mut parsed.command else {
+                        return Err("--speed is only valid with replay".into());
+                    };
+                    *speed = value()?.parse()?;
+                    if speed.is_nan() || *speed <= 0.0 {
+                        return Err("--speed must be positive".into());
+                    }
+                }
+                "--sensor" | "--from" | "--to" | "--format" => {
+                    let Some(Command::Export(export)) = &mut parsed.command else {
+                        return Err(format!("{arg} is only valid with export").into());
+                    };
+                    match arg.as_str() {
+                        "--sensor" => export.sensor = Some(value()?),
+                        "--from" => export.from = Some(value()?),
+                        "--to" => export.to = Some(value()?),
+                        _ => export.format = value()?.parse()?,
+                    }
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {arg}").into()),
+                _ => parsed.config = arg,
+            }
+        }
+
+        Ok(parsed)
+    }
+}
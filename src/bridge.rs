@@ -0,0 +1,46 @@
+//! Raw topics republished to the output broker, so the daemon can stand in for a selective bridge
+
+use crate::config::Bridge;
+use crate::event::{Event, Message, Sender};
+use crate::sensor::Handler;
+use log::warn;
+
+/// Adds a handler republishing each bridged topic to `sources`, sharing the handler of a sensor
+/// reading the same topic since the client only keeps one per topic
+pub fn add(bridges: &[Bridge], retain: bool, tx: &Sender, sources: &mut Vec<(String, Handler)>) {
+    for bridge in bridges {
+        let republish = handler(bridge, retain, tx.clone());
+        match sources.iter_mut().find(|(topic, _)| *topic == bridge.topic) {
+            Some((_, source)) => {
+                let sensor = std::mem::replace(source, Box::new(|_| None));
+                *source = Box::new(move |payload: Vec<u8>| {
+                    republish(payload.clone());
+                    sensor(payload)
+                });
+            }
+            None => sources.push((bridge.topic.clone(), republish)),
+        }
+    }
+}
+
+fn handler(bridge: &Bridge, retain: bool, tx: Sender) -> Handler {
+    let source = bridge.topic.clone();
+    let topic = bridge.output_topic();
+    let broker = bridge.broker.clone();
+    let retain = bridge.retain.unwrap_or(retain);
+    Box::new(move |payload: Vec<u8>| {
+        // messages are published as strings
+        match String::from_utf8(payload) {
+            Ok(payload) => {
+                let _ = tx.send(Event::Publish(Message {
+                    topic: topic.clone(),
+                    payload,
+                    retain,
+                    broker: broker.clone(),
+                }));
+            }
+            Err(_) => warn!("Not bridging non-UTF-8 payload from {source}"),
+        }
+        None
+    })
+}
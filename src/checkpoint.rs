@@ -0,0 +1,82 @@
+//! Sensors' last published values and statistics samples, saved to the `state_file` so they
+//! survive a restart
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Every sensor's state, by ID
+pub type State = BTreeMap<String, SensorState>;
+
+/// (Unix time, value) of each sample, oldest first
+pub type Samples = Vec<(f64, f64)>;
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct SensorState {
+    /// Last value published for each metric, by name
+    #[serde(default)]
+    pub published: BTreeMap<String, Published>,
+    /// Temperature and dewpoint samples for trends
+    #[serde(default)]
+    pub trends: (Samples, Samples),
+    /// Samples of each metric for statistics, by name
+    #[serde(default)]
+    pub samples: BTreeMap<String, Samples>,
+    /// Last payload published when `output_format` is `json`
+    pub json: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Published {
+    pub value: f64,
+    pub payload: String,
+    pub state: String,
+    /// Unix time
+    pub at: f64,
+}
+
+/// Reads the state saved by the last run, which is empty if there isn't any or it can't be read
+pub fn load(path: &Path) -> State {
+    let state = match std::fs::read_to_string(path) {
+        Ok(state) => state,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return State::new(),
+        Err(e) => {
+            warn!("Not restoring state from {}: {e}", path.display());
+            return State::new();
+        }
+    };
+    serde_json::from_str(&state).unwrap_or_else(|e| {
+        warn!("Not restoring state from {}: {e}", path.display());
+        State::new()
+    })
+}
+
+/// Writes `state` to a temporary file that replaces `path`, so a crash while writing leaves the
+/// last checkpoint intact
+pub fn save(path: &Path, state: &State) -> Result<(), Box<dyn Error>> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    std::fs::write(&temporary, serde_json::to_string(state)?)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Unix time of an instant, in seconds
+pub fn unix(instant: Instant) -> f64 {
+    (SystemTime::now() - instant.elapsed())
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// Instant at a Unix time, or `None` if it's before the monotonic clock started
+pub fn instant(unix: f64) -> Option<Instant> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let age = now.saturating_sub(Duration::try_from_secs_f64(unix).ok()?);
+    Instant::now().checked_sub(age)
+}
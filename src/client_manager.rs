@@ -0,0 +1,266 @@
+//! Every broker connection the daemon holds, and which one each message goes to. Each
+//! `mqtt::client::Client` runs its own reader and keep-alive threads, which the client library
+//! gives no way to share, so there's one set per broker rather than a shared event loop.
+
+use crate::compression::OutputCompression;
+use crate::config::{Broker, Config};
+use crate::encryption::Encryption;
+use crate::event::Event;
+use crate::failure;
+use crate::sensor::Handler;
+use crate::trace;
+use log::warn;
+use mqtt::client::Client;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+/// Connections to the broker sensors are read from, the `output_broker`, and the named `brokers`
+pub struct ClientManager {
+    /// `None` for a dry run, which prints messages instead of publishing them
+    source: Option<Client>,
+    output: Option<Client>,
+    named: HashMap<String, Client>,
+    /// The config's `topic_prefix`, prepended to every topic
+    prefix: String,
+    /// Log every subscription and message with `--trace-packets`
+    trace: bool,
+    encryption: Option<Arc<Encryption>>,
+    compression: Option<OutputCompression>,
+}
+
+impl ClientManager {
+    /// Connects to every broker in the config, or `None` if `rx` receives `Event::Shutdown` while
+    /// waiting to retry one
+    pub fn connect(
+        config: &Config,
+        rx: &Receiver<Event>,
+        trace: bool,
+    ) -> Result<Option<Self>, Box<dyn Error>> {
+        let encryption = encryption(config)?;
+        let connect = |broker: &Broker| {
+            let interval = config.retry_interval();
+            let addr = &broker.broker_addr;
+            failure::connect(config.on_failure, interval, rx, addr, || {
+                connect(broker, trace)
+            })
+        };
+        let Some(source) = connect(&config.broker)? else {
+            return Ok(None);
+        };
+        let mut output = None;
+        if let Some(broker) = &config.output_broker {
+            let Some(client) = connect(broker)? else {
+                return Ok(None);
+            };
+            output = Some(client);
+        }
+        let mut named = HashMap::new();
+        for (name, broker) in &config.brokers {
+            let Some(client) = connect(broker)? else {
+                return Ok(None);
+            };
+            named.insert(name.clone(), client);
+        }
+        Ok(Some(Self {
+            source: Some(source),
+            output,
+            named,
+            prefix: config.topic_prefix.clone().unwrap_or_default(),
+            trace,
+            encryption,
+            compression: config
+                .output_compression
+                .as_ref()
+                .map(OutputCompression::new),
+        }))
+    }
+
+    /// Connects to just the broker sensors are read from, once, which every message goes to
+    pub fn connect_source(config: &Config, trace: bool) -> Result<Self, Box<dyn Error>> {
+        let encryption = encryption(config)?;
+        Ok(Self {
+            source: Some(connect(&config.broker, trace)?),
+            output: None,
+            named: HashMap::new(),
+            prefix: config.topic_prefix.clone().unwrap_or_default(),
+            trace,
+            encryption,
+            compression: config
+                .output_compression
+                .as_ref()
+                .map(OutputCompression::new),
+        })
+    }
+
+    /// Prints each message as `TOPIC PAYLOAD` to stdout instead of connecting to any broker, and
+    /// takes no subscriptions
+    pub fn dry_run(config: &Config, trace: bool) -> Result<Self, Box<dyn Error>> {
+        let encryption = encryption(config)?;
+        Ok(Self {
+            source: None,
+            output: None,
+            named: HashMap::new(),
+            prefix: config.topic_prefix.clone().unwrap_or_default(),
+            trace,
+            encryption,
+            compression: config
+                .output_compression
+                .as_ref()
+                .map(OutputCompression::new),
+        })
+    }
+
+    /// Publishes to one of the named `brokers`, or the output broker
+    pub fn publish(&mut self, broker: Option<&str>, topic: &str, payload: &str, retain: bool) {
+        let compressed = self
+            .compression
+            .as_ref()
+            .and_then(|compression| compression.compress(topic, payload));
+        let payload = compressed.as_deref().unwrap_or(payload);
+        let encrypted = self
+            .encryption
+            .as_ref()
+            .filter(|encryption| encryption.applies(topic))
+            .map(|encryption| encryption.encrypt(payload));
+        let payload = encrypted.as_deref().unwrap_or(payload);
+        let topic = format!("{}{topic}", self.prefix);
+        let trace = self.trace;
+        let Some(source) = &mut self.source else {
+            // the same layout replay reads, so a dry run's output can be compared between configs
+            println!("{topic} {payload}");
+            return;
+        };
+        let client = match broker {
+            Some(name) => self.named.get_mut(name).expect("Output to unknown broker"),
+            None => self.output.as_mut().unwrap_or(source),
+        };
+        publish(client, &topic, payload, retain, trace);
+    }
+
+    /// Subscribes to one of sensors' source topics on the broker they're read from
+    pub fn subscribe_source(
+        &mut self,
+        topic: &str,
+        handler: Handler,
+    ) -> Result<(), Box<dyn Error>> {
+        let handler = self.decrypting(topic, handler);
+        let topic = format!("{}{topic}", self.prefix);
+        let Some(source) = &mut self.source else {
+            return Ok(());
+        };
+        subscribe(source, topic, handler, self.trace)
+    }
+
+    /// Subscribes to a topic on the output broker, like commands and Home Assistant's status
+    pub fn subscribe_output(
+        &mut self,
+        topic: &str,
+        handler: Handler,
+    ) -> Result<(), Box<dyn Error>> {
+        let handler = self.decrypting(topic, handler);
+        let topic = format!("{}{topic}", self.prefix);
+        let Some(client) = self.output.as_mut().or(self.source.as_mut()) else {
+            return Ok(());
+        };
+        subscribe(client, topic, handler, self.trace)
+    }
+
+    /// Wraps `handler` to decrypt payloads first if `topic` is encrypted, dropping any that can't be
+    fn decrypting(&self, topic: &str, handler: Handler) -> Handler {
+        let Some(encryption) = self
+            .encryption
+            .as_ref()
+            .filter(|encryption| encryption.applies(topic))
+        else {
+            return handler;
+        };
+        let encryption = Arc::clone(encryption);
+        let topic = topic.to_string();
+        Box::new(move |payload| match encryption.decrypt(&payload) {
+            Ok(payload) => handler(payload),
+            Err(e) => {
+                warn!(
+                    target: "mqtt::client",
+                    topic = topic.as_str();
+                    "Dropping payload on {topic}: {e}"
+                );
+                None
+            }
+        })
+    }
+
+    pub fn disconnect(self) {
+        for mut client in self.named.into_values() {
+            client.disconnect();
+        }
+        if let Some(mut output) = self.output {
+            output.disconnect();
+        }
+        if let Some(mut source) = self.source {
+            source.disconnect();
+        }
+    }
+}
+
+/// The config's payload encryption, shared with decrypting handlers
+fn encryption(config: &Config) -> Result<Option<Arc<Encryption>>, Box<dyn Error>> {
+    Ok(config
+        .encryption
+        .as_ref()
+        .map(Encryption::new)
+        .transpose()?
+        .map(Arc::new))
+}
+
+/// Publishes, logging the message first with `--trace-packets`
+fn publish(client: &mut Client, topic: &str, payload: &str, retain: bool, trace: bool) {
+    if trace {
+        trace::publish(topic, payload, retain);
+    }
+    client.publish(topic, payload, retain);
+}
+
+/// Subscribes, logging the subscription and every message received with `--trace-packets`
+fn subscribe(
+    client: &mut Client,
+    topic: String,
+    handler: Handler,
+    trace: bool,
+) -> Result<(), Box<dyn Error>> {
+    let handler = if trace {
+        trace::subscribe(&topic);
+        trace::received(topic.clone(), handler)
+    } else {
+        handler
+    };
+    client.subscribe(&topic, handler)?;
+    Ok(())
+}
+
+fn connect(broker: &Broker, trace: bool) -> Result<Client, Box<dyn Error>> {
+    let client_id = &broker.client_id;
+    if client_id.len() > 0xFF {
+        return Err("Client ID too long".into());
+    }
+
+    let username = &broker.username;
+    if username.len() > 0xFF {
+        return Err("Username too long".into());
+    }
+
+    let password = &broker.password;
+    if password.len() > 0xFF {
+        return Err("Password too long".into());
+    }
+
+    let keep_alive = 60;
+    if trace {
+        trace::connect(broker, keep_alive);
+    }
+    let mut client = Client::new(client_id, username, password, keep_alive);
+    client.connect(&broker.broker_addr)?;
+
+    Ok(client)
+}
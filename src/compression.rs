@@ -0,0 +1,157 @@
+//! gzip or zstd compression of payloads, read from sensors that compress theirs and applied to
+//! selected output topics
+//!
+//! Compressed output payloads are base64, since the client only publishes text.
+
+use crate::config;
+use crate::topic;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::Deserialize;
+use std::io::{self, Read};
+
+/// Largest decompressed payload, so a small message can't expand to fill memory
+const MAX_DECOMPRESSED: u64 = 1024 * 1024;
+
+/// Compression of a sensor's payloads, e.g. from firmware sending bulky JSON over a metered link
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression named by a topic's `.gz` or `.zst` suffix, for firmware that marks compressed
+    /// payloads that way
+    pub fn from_topic(topic: &str) -> Option<Self> {
+        if topic.ends_with(".gz") {
+            Some(Self::Gzip)
+        } else if topic.ends_with(".zst") {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn compress(self, payload: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        match self {
+            Self::Gzip => {
+                flate2::read::GzEncoder::new(payload, flate2::Compression::default())
+                    .read_to_end(&mut compressed)
+                    .expect("Compressing in memory can't fail");
+            }
+            Self::Zstd => {
+                compressed =
+                    zstd::encode_all(payload, 0).expect("Compressing in memory can't fail");
+            }
+        }
+        compressed
+    }
+
+    pub fn decompress(self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        let decoder: Box<dyn Read> = match self {
+            Self::Gzip => Box::new(flate2::read::GzDecoder::new(payload)),
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(payload)?),
+        };
+        let mut decompressed = Vec::new();
+        decoder
+            .take(MAX_DECOMPRESSED + 1)
+            .read_to_end(&mut decompressed)?;
+        if decompressed.len() as u64 > MAX_DECOMPRESSED {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decompressed payload is over 1 MiB",
+            ));
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Compression of payloads published to the config's `output_compression` topics
+pub struct OutputCompression {
+    algorithm: Compression,
+    min_size: usize,
+    /// Topic filters, which may have `+` and `#` wildcards
+    topics: Vec<String>,
+}
+
+impl OutputCompression {
+    pub fn new(config: &config::OutputCompression) -> Self {
+        Self {
+            algorithm: config.algorithm,
+            min_size: config.min_size,
+            topics: config.topics.clone(),
+        }
+    }
+
+    /// The payload to publish to `topic` in place of `payload`, if it's compressed
+    pub fn compress(&self, topic: &str, payload: &str) -> Option<String> {
+        if payload.len() < self.min_size
+            || !self
+                .topics
+                .iter()
+                .any(|filter| topic::matches(filter, topic))
+        {
+            return None;
+        }
+        Some(BASE64.encode(self.algorithm.compress(payload.as_bytes())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gzip(min_size: usize) -> OutputCompression {
+        OutputCompression::new(&config::OutputCompression {
+            algorithm: Compression::Gzip,
+            min_size,
+            topics: vec![String::from("humidity/+/json")],
+        })
+    }
+
+    #[test]
+    fn from_topic() {
+        assert!(matches!(
+            Compression::from_topic("sensors/attic.gz"),
+            Some(Compression::Gzip)
+        ));
+        assert!(matches!(
+            Compression::from_topic("sensors/attic.zst"),
+            Some(Compression::Zstd)
+        ));
+        assert!(Compression::from_topic("sensors/attic").is_none());
+        assert!(Compression::from_topic("sensors/attic.gzip").is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let payload = r#"{"dewpoint":11.2,"humidity":52.0,"temperature":21.5}"#;
+        let compressed = gzip(0).compress("humidity/attic/json", payload).unwrap();
+        let compressed = BASE64.decode(compressed).unwrap();
+        let decompressed = Compression::Gzip.decompress(&compressed).unwrap();
+        assert_eq!(decompressed, payload.as_bytes());
+    }
+
+    #[test]
+    fn only_matching_topics_over_min_size() {
+        let compression = gzip(16);
+        assert!(compression
+            .compress("humidity/attic/json", "12.5")
+            .is_none());
+        assert!(compression
+            .compress("humidity/attic/dewpoint", "a longer payload than 16 bytes")
+            .is_none());
+        assert!(compression
+            .compress("humidity/attic/json", "a longer payload than 16 bytes")
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_oversized_payloads() {
+        let bomb = Compression::Gzip.compress(&vec![0; 2 * 1024 * 1024]);
+        assert!(Compression::Gzip.decompress(&bomb).is_err());
+    }
+}
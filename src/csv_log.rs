@@ -0,0 +1,210 @@
+use crate::config::{self, Sensor};
+use crate::sink::{Record, Sink};
+use crate::timestamp;
+use log::warn;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Values every record has before the sensor's metrics
+const COLUMNS: [&str; 4] = ["raw_temperature", "raw_humidity", "temperature", "humidity"];
+
+/// A CSV file per sensor with a row for every reading, rotated daily and/or by size
+pub struct CsvLog {
+    config: config::Csv,
+    /// Columns after the timestamp, by sensor ID
+    columns: HashMap<String, Vec<String>>,
+    /// File currently written for each sensor, its path, and its size
+    files: HashMap<String, (PathBuf, File, u64)>,
+}
+
+impl CsvLog {
+    pub fn new(config: &config::Csv, sensors: &[Sensor]) -> io::Result<Self> {
+        fs::create_dir_all(&config.directory)?;
+        let columns = sensors
+            .iter()
+            .map(|sensor| {
+                let metrics = sensor
+                    .metrics
+                    .iter()
+                    .filter(|metric| !metric.is_text())
+                    .map(|metric| metric.name());
+                let columns = COLUMNS.into_iter().chain(metrics).map(String::from);
+                (sensor.id().to_string(), columns.collect())
+            })
+            .collect();
+        Ok(Self {
+            config: config.clone(),
+            columns,
+            files: HashMap::new(),
+        })
+    }
+
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        let Some(columns) = self.columns.get(&record.sensor_id) else {
+            return Ok(());
+        };
+        let timestamp = timestamp::format(record.time);
+        let name = if self.config.daily {
+            format!("{}-{}", record.sensor_id, &timestamp[..10])
+        } else {
+            record.sensor_id.clone()
+        };
+        let path = self.config.directory.join(format!("{name}.csv"));
+
+        let rotate = self.config.max_size.is_some_and(|max_size| {
+            self.files
+                .get(&record.sensor_id)
+                .is_some_and(|(open, _, size)| *open == path && *size >= max_size)
+        });
+        if rotate {
+            self.files.remove(&record.sensor_id);
+            let rotated = (1..u32::MAX)
+                .map(|n| self.config.directory.join(format!("{name}.{n}.csv")))
+                .find(|rotated| !rotated.exists())
+                .ok_or_else(|| {
+                    io::Error::other(format!("No free name to rotate {} to", path.display()))
+                })?;
+            fs::rename(&path, rotated)?;
+        }
+        let current = self.files.get(&record.sensor_id);
+        if current.is_none_or(|(open, ..)| *open != path) {
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let size = file.metadata()?.len();
+            self.files
+                .insert(record.sensor_id.clone(), (path, file, size));
+        }
+        let Some((_, file, size)) = self.files.get_mut(&record.sensor_id) else {
+            return Ok(());
+        };
+        if *size == 0 {
+            let header = format!("timestamp,{}\n", columns.join(","));
+            file.write_all(header.as_bytes())?;
+            *size += header.len() as u64;
+        }
+
+        let mut row = timestamp;
+        for column in columns {
+            row.push(',');
+            if let Some((_, value)) = record.values.iter().find(|(name, _)| name == column) {
+                row.push_str(&value.to_string());
+            }
+        }
+        row.push('\n');
+        file.write_all(row.as_bytes())?;
+        *size += row.len() as u64;
+        Ok(())
+    }
+}
+
+/// Deletes CSV files in `directory` last written before `before`, returning how many were deleted
+pub fn prune(directory: &Path, before: SystemTime) -> io::Result<usize> {
+    let mut deleted = 0;
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "csv")
+            && fs::metadata(&path)?.modified()? < before
+        {
+            fs::remove_file(&path)?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+impl Sink for CsvLog {
+    fn write(&mut self, record: &Record) {
+        if let Err(e) = self.append(record) {
+            warn!("Error writing {} to CSV: {e}", record.sensor_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// An empty directory in the temp directory
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("mqtt_dewpoint_{name}"));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn record(secs: u64) -> Record {
+        Record {
+            sensor_id: String::from("attic"),
+            time: UNIX_EPOCH + Duration::from_secs(secs),
+            values: vec![
+                (String::from("temperature"), 21.5),
+                (String::from("dewpoint"), 9.2),
+            ],
+        }
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let directory = directory("csv_rotate");
+        let mut csv = CsvLog {
+            config: config::Csv {
+                directory: directory.clone(),
+                daily: false,
+                max_size: Some(60),
+            },
+            columns: HashMap::from([(
+                String::from("attic"),
+                vec![String::from("temperature"), String::from("dewpoint")],
+            )]),
+            files: HashMap::new(),
+        };
+        for secs in [0, 60, 120] {
+            csv.append(&record(secs)).unwrap();
+        }
+        drop(csv);
+
+        // the header and first row reach max_size, so each later row starts a new file
+        let read = |name: &str| fs::read_to_string(directory.join(name)).unwrap();
+        assert_eq!(
+            read("attic.1.csv"),
+            "timestamp,temperature,dewpoint\n1970-01-01T00:00:00Z,21.5,9.2\n"
+        );
+        assert_eq!(
+            read("attic.2.csv"),
+            "timestamp,temperature,dewpoint\n1970-01-01T00:01:00Z,21.5,9.2\n"
+        );
+        assert_eq!(
+            read("attic.csv"),
+            "timestamp,temperature,dewpoint\n1970-01-01T00:02:00Z,21.5,9.2\n"
+        );
+    }
+
+    #[test]
+    fn prunes_old_csv_files() {
+        let directory = directory("csv_prune");
+        let old = UNIX_EPOCH + Duration::from_secs(24 * 60 * 60);
+        for (name, modified) in [
+            ("attic-1970-01-02.csv", old),
+            ("attic-1970-01-02.1.csv", old),
+            ("notes.txt", old),
+            ("attic.csv", SystemTime::now()),
+        ] {
+            File::create(directory.join(name))
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let before = SystemTime::now() - Duration::from_secs(60 * 60);
+        assert_eq!(prune(&directory, before).unwrap(), 2);
+        let mut left: Vec<String> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["attic.csv", "notes.txt"]);
+    }
+}
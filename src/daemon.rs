@@ -0,0 +1,29 @@
+//! Running in the background without a service manager
+
+use crate::args::Args;
+use daemonize::Daemonize;
+use std::error::Error;
+use std::fs::OpenOptions;
+
+/// Forks to the background, returning in the child. The working directory is kept so relative
+/// config paths still work.
+pub fn start(args: &Args) -> Result<(), Box<dyn Error>> {
+    let mut daemonize = Daemonize::new().working_directory(std::env::current_dir()?);
+    if let Some(pid_file) = &args.pid_file {
+        // owned by the user being switched to, so it can be cleaned up by them
+        daemonize = daemonize.pid_file(pid_file).chown_pid_file(true);
+    }
+    if let Some(user) = &args.user {
+        daemonize = daemonize.user(user.as_str());
+    }
+    if let Some(log_file) = &args.log_file {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)
+            .map_err(|e| format!("{log_file}: {e}"))?;
+        daemonize = daemonize.stdout(file.try_clone()?).stderr(file);
+    }
+    daemonize.start()?;
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use crate::config::Diagnostics;
+use crate::discovery;
+use crate::event::Message;
+use crate::histogram::Histogram;
+use crate::timestamp;
+use std::collections::BTreeMap;
+use std::time::{Instant, SystemTime};
+
+// (metric, component) of each published value
+const UPTIME: (&str, &str) = ("uptime", "sensor");
+const MESSAGES: (&str, &str) = ("messages", "sensor");
+const LAST_ERROR: (&str, &str) = ("last_error", "sensor");
+const CONNECTED: (&str, &str) = ("connected", "binary_sensor");
+
+/// What the daemon has been up to since it started
+pub struct Stats {
+    started: Instant,
+    /// Messages received on sensors' source topics
+    pub messages: u64,
+    /// Readings calculated from those messages
+    pub readings: u64,
+    /// Messages published
+    pub published: u64,
+    /// Bytes of payload published
+    pub published_bytes: u64,
+    /// Messages published to each topic
+    pub published_topics: BTreeMap<String, u64>,
+    /// Messages dropped by the publish rate limits
+    pub rate_limited: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+    /// When each sensor last received a message, by ID
+    pub last_seen: BTreeMap<String, SystemTime>,
+    /// How long each sensor's handlers take with a message, by ID
+    pub handler_time: BTreeMap<String, Histogram>,
+    /// Time from a message arriving to the values calculated from it being published, by sensor ID
+    pub latency: BTreeMap<String, Histogram>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            messages: 0,
+            readings: 0,
+            published: 0,
+            published_bytes: 0,
+            published_topics: BTreeMap::new(),
+            rate_limited: 0,
+            errors: 0,
+            last_error: None,
+            last_seen: BTreeMap::new(),
+            handler_time: BTreeMap::new(),
+            latency: BTreeMap::new(),
+        }
+    }
+}
+
+/// Current state of every diagnostic entity
+pub fn update(diagnostics: &Diagnostics, stats: &Stats) -> Vec<Message> {
+    [
+        (UPTIME, stats.started.elapsed().as_secs().to_string()),
+        (MESSAGES, stats.messages.to_string()),
+        (
+            LAST_ERROR,
+            stats
+                .last_error
+                .clone()
+                .unwrap_or_else(|| String::from("none")),
+        ),
+        (CONNECTED, String::from("ON")),
+    ]
+    .into_iter()
+    .map(|((metric, component), payload)| Message {
+        topic: diagnostics.state_topic(metric, component),
+        payload,
+        retain: true,
+        broker: None,
+    })
+    .collect()
+}
+
+/// JSON summary of the daemon's status for its `status_topic`
+pub fn status(stats: &Stats, broker: &str) -> String {
+    let last_seen: BTreeMap<&str, String> = stats
+        .last_seen
+        .iter()
+        .map(|(id, &at)| (id.as_str(), timestamp::format(at)))
+        .collect();
+    let histograms = |histograms: &BTreeMap<String, Histogram>| -> serde_json::Value {
+        histograms
+            .iter()
+            .map(|(id, histogram)| (id.clone(), histogram.json()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    };
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "uptime": stats.started.elapsed().as_secs(),
+        "broker": broker,
+        "messages": stats.messages,
+        "readings": stats.readings,
+        "published": stats.published,
+        "published_bytes": stats.published_bytes,
+        "published_topics": stats.published_topics,
+        "rate_limited": stats.rate_limited,
+        "errors": stats.errors,
+        "last_error": stats.last_error,
+        "last_seen": last_seen,
+        "handler_time": histograms(&stats.handler_time),
+        "latency": histograms(&stats.latency),
+    })
+    .to_string()
+}
+
+/// JSON payload for the `error_topic`, including how many errors were dropped by its rate limit
+/// since the last one published
+pub fn error(sensor_id: &str, topic: Option<String>, message: &str, suppressed: u64) -> String {
+    serde_json::json!({
+        "timestamp": timestamp::now(),
+        "sensor_id": sensor_id,
+        "topic": topic,
+        "message": message,
+        "suppressed": suppressed,
+    })
+    .to_string()
+}
+
+/// Marks the daemon as disconnected, on shutdown
+pub fn disconnected(diagnostics: &Diagnostics) -> Message {
+    Message {
+        topic: diagnostics.state_topic(CONNECTED.0, CONNECTED.1),
+        payload: String::from("OFF"),
+        retain: true,
+        broker: None,
+    }
+}
+
+/// Topics and payloads of the Home Assistant discovery configs for the daemon's diagnostics
+pub fn discovery_configs(diagnostics: &Diagnostics, daemon: Option<&str>) -> Vec<(String, String)> {
+    [
+        (UPTIME, "Uptime", Some("duration"), Some("s")),
+        (MESSAGES, "Messages", None, None),
+        (LAST_ERROR, "Last error", None, None),
+        (CONNECTED, "Connected", Some("connectivity"), None),
+    ]
+    .into_iter()
+    .filter_map(|((metric, component), label, device_class, unit)| {
+        let topic = diagnostics.discovery_topic(metric, component)?;
+        let numeric = unit.is_some() || metric == MESSAGES.0;
+        let payload = serde_json::json!({
+            "name": format!("{} {label}", diagnostics.name()),
+            "unique_id": discovery::unique_id(&diagnostics.id, metric),
+            "device": discovery::device(&diagnostics.id, diagnostics.name()),
+            "device_class": device_class,
+            "state_class": numeric.then_some("total_increasing"),
+            "entity_category": "diagnostic",
+            "state_topic": diagnostics.state_topic(metric, component),
+            "unit_of_measurement": unit,
+        });
+        // the connected sensor should stay available to show the daemon going away
+        let daemon = daemon.filter(|_| metric != CONNECTED.0);
+        Some((topic, discovery::payload(payload, daemon)))
+    })
+    .collect()
+}
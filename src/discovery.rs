@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::path::Path;
+
+/// Prefix of unique IDs and device identifiers, keeping them distinct from other integrations
+const PREFIX: &str = "mqtt_dewpoint";
+
+/// Unique ID of the entity publishing `key` for a sensor, ventilation advisor, or group
+pub fn unique_id(id: &str, key: &str) -> String {
+    format!("{PREFIX}_{id}_{key}")
+}
+
+/// Device block shared by every entity of a sensor, ventilation advisor, or group
+pub fn device(id: &str, name: &str) -> serde_json::Value {
+    serde_json::json!({
+        "identifiers": [format!("{PREFIX}_{id}")],
+        "name": name,
+        "model": "mqtt_dewpoint",
+        "sw_version": env!("CARGO_PKG_VERSION"),
+    })
+}
+
+/// Serializes a discovery config. Home Assistant rejects null for most options, so they're left
+/// out instead. With a `daemon` availability topic the entity is only available while both the
+/// daemon and its own `availability_topic` (if any) are online.
+pub fn payload(mut config: serde_json::Value, daemon: Option<&str>) -> String {
+    if let Some(options) = config.as_object_mut() {
+        options.retain(|_, value| !value.is_null());
+        if let Some(daemon) = daemon {
+            let mut availability = vec![serde_json::json!({ "topic": daemon })];
+            if let Some(topic) = options.remove("availability_topic") {
+                availability.push(serde_json::json!({ "topic": topic }));
+            }
+            options.insert(String::from("availability"), availability.into());
+            options.insert(String::from("availability_mode"), "all".into());
+        }
+    }
+    config.to_string()
+}
+
+/// Prepends the config's `topic_prefix` to the topics in a serialized discovery config, since
+/// Home Assistant subscribes to them as they are
+pub fn prefix_topics(payload: &str, prefix: &str) -> String {
+    let Ok(mut config) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return payload.to_string();
+    };
+    if let Some(options) = config.as_object_mut() {
+        for (key, value) in options.iter_mut() {
+            match value {
+                serde_json::Value::String(topic) if key.ends_with("_topic") => {
+                    topic.insert_str(0, prefix);
+                }
+                serde_json::Value::Array(availability) if key == "availability" => {
+                    for entry in availability {
+                        if let Some(serde_json::Value::String(topic)) = entry.get_mut("topic") {
+                            topic.insert_str(0, prefix);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    config.to_string()
+}
+
+/// Discovery topics listed in `cache` by the last run that aren't in `topics`, replacing the
+/// cache's contents with `topics`
+pub fn removed(cache: &Path, topics: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+    let previous = match std::fs::read_to_string(cache) {
+        Ok(previous) => previous,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(format!("{}: {e}", cache.display()).into()),
+    };
+    let removed = previous
+        .lines()
+        .filter(|topic| !topic.is_empty() && !topics.contains(topic))
+        .map(String::from)
+        .collect();
+    std::fs::write(cache, topics.join("\n")).map_err(|e| format!("{}: {e}", cache.display()))?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn unique_ids() {
+        assert_eq!(
+            unique_id("attic", "dewpoint"),
+            "mqtt_dewpoint_attic_dewpoint"
+        );
+        assert_eq!(
+            unique_id("0x00158d0002c9119d", "dewpoint_max_24h"),
+            "mqtt_dewpoint_0x00158d0002c9119d_dewpoint_max_24h"
+        );
+    }
+
+    #[test]
+    fn payload_leaves_out_nulls() {
+        let config = json!({
+            "name": "attic Dewpoint",
+            "availability_topic": "humidity/attic/availability",
+            "unit_of_measurement": null,
+        });
+        assert_eq!(
+            serde_json::from_str::<Value>(&payload(config, None)).unwrap(),
+            json!({
+                "name": "attic Dewpoint",
+                "availability_topic": "humidity/attic/availability",
+            })
+        );
+    }
+
+    #[test]
+    fn payload_depends_on_daemon() {
+        let config = json!({
+            "name": "attic Dewpoint",
+            "availability_topic": "humidity/attic/availability",
+        });
+        assert_eq!(
+            serde_json::from_str::<Value>(&payload(config, Some("mqtt_dewpoint/availability")))
+                .unwrap(),
+            json!({
+                "name": "attic Dewpoint",
+                "availability": [
+                    {"topic": "mqtt_dewpoint/availability"},
+                    {"topic": "humidity/attic/availability"},
+                ],
+                "availability_mode": "all",
+            })
+        );
+
+        let config = json!({ "name": "attic Dewpoint", "availability_topic": null });
+        assert_eq!(
+            serde_json::from_str::<Value>(&payload(config, Some("mqtt_dewpoint/availability")))
+                .unwrap(),
+            json!({
+                "name": "attic Dewpoint",
+                "availability": [{"topic": "mqtt_dewpoint/availability"}],
+                "availability_mode": "all",
+            })
+        );
+    }
+}
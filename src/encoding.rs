@@ -0,0 +1,172 @@
+#[cfg(not(feature = "protobuf"))]
+use crate::config::ProtobufDisabled;
+use serde::Deserialize;
+
+/// How a sensor's payloads are encoded, e.g. binary formats from bandwidth-conscious firmware
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadEncoding {
+    #[default]
+    Json,
+    Cbor,
+    Msgpack,
+    /// A protobuf message with the values in these fields
+    #[cfg(feature = "protobuf")]
+    Protobuf(ProtobufFields),
+    #[cfg(not(feature = "protobuf"))]
+    Protobuf(ProtobufDisabled),
+}
+
+/// Field numbers of the values in a protobuf message, which may be `double`, `float`, `int32`, or
+/// `int64`
+#[cfg(feature = "protobuf")]
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProtobufFields {
+    pub temperature: u64,
+    pub humidity: u64,
+    pub pressure: Option<u64>,
+}
+
+impl PayloadEncoding {
+    /// Re-encodes a payload as JSON, which everything reading payloads expects
+    pub fn to_json(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let value: serde_json::Value = match self {
+            Self::Json => return Ok(payload.to_vec()),
+            Self::Cbor => ciborium::from_reader(payload).map_err(|e| e.to_string())?,
+            Self::Msgpack => rmp_serde::from_slice(payload).map_err(|e| e.to_string())?,
+            #[cfg(feature = "protobuf")]
+            Self::Protobuf(fields) => protobuf(payload, fields)?,
+            #[cfg(not(feature = "protobuf"))]
+            Self::Protobuf(disabled) => match disabled {},
+        };
+        serde_json::to_vec(&value).map_err(|e| e.to_string())
+    }
+}
+
+/// Reads the configured fields of a protobuf message into a JSON object, ignoring the rest
+#[cfg(feature = "protobuf")]
+fn protobuf(mut payload: &[u8], fields: ProtobufFields) -> Result<serde_json::Value, String> {
+    let names = [
+        ("temperature", Some(fields.temperature)),
+        ("humidity", Some(fields.humidity)),
+        ("pressure", fields.pressure),
+    ];
+    let mut values = serde_json::Map::new();
+    while !payload.is_empty() {
+        let key = varint(&mut payload)?;
+        let value = match key & 7 {
+            // negative int32 and int64 values are sign extended to 64 bits, and any beyond 2^53
+            // are far outside a plausible reading anyway
+            #[allow(clippy::cast_possible_wrap, clippy::cast_precision_loss)]
+            0 => Some(varint(&mut payload)? as i64 as f64),
+            1 => Some(f64::from_le_bytes(fixed(&mut payload)?)),
+            2 => {
+                let len = usize::try_from(varint(&mut payload)?).map_err(|e| e.to_string())?;
+                payload = payload.get(len..).ok_or("Truncated protobuf field")?;
+                None
+            }
+            5 => Some(f64::from(f32::from_le_bytes(fixed(&mut payload)?))),
+            wire_type => return Err(format!("Unsupported protobuf wire type {wire_type}")),
+        };
+        let number = Some(key >> 3);
+        if let (Some(value), Some((name, _))) = (value, names.iter().find(|(_, n)| *n == number)) {
+            values.insert(name.to_string(), serde_json::json!(value));
+        }
+    }
+    Ok(values.into())
+}
+
+#[cfg(feature = "protobuf")]
+fn varint(payload: &mut &[u8]) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = payload.split_first().ok_or("Truncated protobuf varint")?;
+        *payload = rest;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(String::from("Protobuf varint is too long"))
+}
+
+#[cfg(feature = "protobuf")]
+fn fixed<const N: usize>(payload: &mut &[u8]) -> Result<[u8; N], String> {
+    let bytes = payload
+        .get(..N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("Truncated protobuf field")?;
+    *payload = &payload[N..];
+    Ok(bytes)
+}
+
+#[cfg(all(test, feature = "protobuf"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FIELDS: ProtobufFields = ProtobufFields {
+        temperature: 1,
+        humidity: 2,
+        pressure: Some(3),
+    };
+
+    #[test]
+    fn fixed64_and_fixed32() {
+        let mut payload = vec![0x09];
+        payload.extend(21.5_f64.to_le_bytes());
+        payload.push(0x15);
+        payload.extend(45.0_f32.to_le_bytes());
+        assert_eq!(
+            protobuf(&payload, FIELDS),
+            Ok(json!({"temperature": 21.5, "humidity": 45.0}))
+        );
+    }
+
+    #[test]
+    fn varints() {
+        // 300 takes two bytes, and -5 as an int32 is sign extended to ten
+        let mut payload = vec![0x08, 0xAC, 0x02, 0x18];
+        payload.extend([0xFB, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]);
+        assert_eq!(
+            protobuf(&payload, FIELDS),
+            Ok(json!({"temperature": 300.0, "pressure": -5.0}))
+        );
+    }
+
+    #[test]
+    fn skips_other_fields() {
+        // a string in field 4 and a varint in field 5
+        let payload = [0x22, 0x02, b'a', b'b', 0x28, 0x01, 0x10, 0x2D];
+        assert_eq!(protobuf(&payload, FIELDS), Ok(json!({"humidity": 45.0})));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(
+            protobuf(&[0x09, 0x00, 0x00], FIELDS),
+            Err(String::from("Truncated protobuf field"))
+        );
+        assert_eq!(
+            protobuf(&[0x15, 0x00], FIELDS),
+            Err(String::from("Truncated protobuf field"))
+        );
+        assert_eq!(
+            protobuf(&[0x08, 0x80], FIELDS),
+            Err(String::from("Truncated protobuf varint"))
+        );
+        assert_eq!(
+            protobuf(&[0x22, 0x05, b'a'], FIELDS),
+            Err(String::from("Truncated protobuf field"))
+        );
+    }
+
+    #[test]
+    fn unsupported_wire_type() {
+        assert_eq!(
+            protobuf(&[0x0B], FIELDS),
+            Err(String::from("Unsupported protobuf wire type 3"))
+        );
+    }
+}
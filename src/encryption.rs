@@ -0,0 +1,127 @@
+//! ChaCha20-Poly1305 encryption of payloads on selected topics, for publishing through a shared
+//! broker that shouldn't see them
+//!
+//! Encrypted payloads are the base64 of a random 12 byte nonce followed by the ciphertext and its
+//! tag, since the client only publishes text.
+
+use crate::config;
+use crate::topic;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce};
+use std::error::Error;
+
+const NONCE_LEN: usize = 12;
+
+pub struct Encryption {
+    cipher: ChaCha20Poly1305,
+    /// Topic filters, which may have `+` and `#` wildcards
+    topics: Vec<String>,
+}
+
+impl Encryption {
+    pub fn new(config: &config::Encryption) -> Result<Self, Box<dyn Error>> {
+        let key = BASE64
+            .decode(&config.key)
+            .map_err(|e| format!("Encryption key isn't base64: {e}"))?;
+        let cipher = ChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|_| "Encryption key must be 32 bytes")?;
+        Ok(Self {
+            cipher,
+            topics: config.topics.clone(),
+        })
+    }
+
+    /// Whether payloads on `topic` are encrypted
+    pub fn applies(&self, topic: &str) -> bool {
+        self.topics
+            .iter()
+            .any(|filter| topic::matches(filter, topic))
+    }
+
+    pub fn encrypt(&self, payload: &str) -> String {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, payload.as_bytes())
+            .expect("Payload too large to encrypt");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        BASE64.encode(sealed)
+    }
+
+    pub fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let sealed = BASE64
+            .decode(payload.trim_ascii())
+            .map_err(|e| format!("not base64: {e}"))?;
+        if sealed.len() < NONCE_LEN {
+            return Err(String::from("too short to be encrypted"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| String::from("can't be decrypted with the key"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_key(key: &[u8; 32]) -> Encryption {
+        Encryption::new(&config::Encryption {
+            key: BASE64.encode(key),
+            topics: vec![
+                String::from("humidity/+/dewpoint"),
+                String::from("remote/#"),
+            ],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn applies() {
+        let encryption = with_key(&[7; 32]);
+        assert!(encryption.applies("humidity/kitchen/dewpoint"));
+        assert!(encryption.applies("remote/x/y"));
+        assert!(!encryption.applies("humidity/kitchen/frost_point"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let encryption = with_key(&[7; 32]);
+        let sealed = encryption.encrypt("12.3");
+        assert_ne!(sealed, "12.3");
+        // a new nonce each time
+        assert_ne!(sealed, encryption.encrypt("12.3"));
+        assert_eq!(encryption.decrypt(sealed.as_bytes()).unwrap(), b"12.3");
+    }
+
+    #[test]
+    fn rejects_tampering_and_other_keys() {
+        let encryption = with_key(&[7; 32]);
+        let sealed = encryption.encrypt("12.3");
+        assert!(with_key(&[8; 32]).decrypt(sealed.as_bytes()).is_err());
+
+        let mut tampered = BASE64.decode(&sealed).unwrap();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(encryption
+            .decrypt(BASE64.encode(tampered).as_bytes())
+            .is_err());
+
+        assert!(encryption.decrypt(b"not base64!").is_err());
+        assert!(encryption
+            .decrypt(BASE64.encode([0; 4]).as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn key_must_be_32_bytes() {
+        let config = config::Encryption {
+            key: BASE64.encode([0; 16]),
+            topics: Vec::new(),
+        };
+        assert!(Encryption::new(&config).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(feature = "otlp")]
+use crate::otlp::Span;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+#[derive(Clone)]
+pub struct Message {
+    pub topic: String,
+    pub payload: String,
+    pub retain: bool,
+    /// Name of one of the config's `brokers`, or `None` for the default output broker
+    pub broker: Option<String>,
+}
+
+/// Work handed from subscription handlers and signal handlers to the main thread
+pub enum Event {
+    Publish(Message),
+    Reading {
+        sensor_id: String,
+        latest: Latest,
+        /// When the message the reading was calculated from arrived, which was published by the
+        /// time the main thread sees this
+        received: Instant,
+        /// Raw and calibrated temperature (°C) and humidity, and each numeric metric, for sinks
+        values: Vec<(String, f64)>,
+    },
+    /// Publish discovery configs and the last state again, e.g. after Home Assistant restarts
+    Announce,
+    /// A message arrived on one of a sensor's source topics
+    Received {
+        sensor_id: String,
+    },
+    /// A sensor's handler finished with a message after `duration`
+    Handled {
+        sensor_id: String,
+        duration: Duration,
+    },
+    /// Something went wrong processing a message for a sensor, already logged
+    Error {
+        sensor_id: String,
+        /// Topic of the message, if it was a problem with the message itself
+        topic: Option<String>,
+        message: String,
+    },
+    /// Time to publish the daemon's diagnostics
+    Diagnostics,
+    /// Time to publish the daemon's status
+    Status,
+    /// Finished work to export to the OpenTelemetry collector
+    #[cfg(feature = "otlp")]
+    Span(Span),
+    /// Time to export to the OpenTelemetry collector
+    #[cfg(feature = "otlp")]
+    Export,
+    /// Time to save sensors' state to the `state_file`
+    Checkpoint,
+    Shutdown,
+}
+
+pub type Sender = mpsc::Sender<Event>;
+
+/// A sensor's latest calibrated values, for features comparing multiple sensors
+#[derive(Clone, Copy)]
+pub struct Latest {
+    /// °C
+    pub temperature: f64,
+    /// Relative humidity, %
+    pub humidity: f64,
+    /// °C
+    pub dewpoint: f64,
+}
+
+/// Latest values from each sensor by ID, and when they were received
+pub type Readings = HashMap<String, (Latest, Instant)>;
@@ -0,0 +1,90 @@
+//! The `export` subcommand, writing stored history to stdout
+
+use crate::args::{Export, ExportFormat};
+use crate::checkpoint;
+use crate::config::Config;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+use crate::timestamp;
+use std::error::Error;
+use std::io::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// One stored value
+#[derive(serde::Serialize)]
+pub struct Row {
+    pub sensor: String,
+    pub metric: String,
+    pub value: f64,
+    /// ISO 8601 in UTC
+    pub timestamp: String,
+}
+
+/// Writes history from the `sqlite` database, or failing that the statistics samples in the
+/// `state_file`
+pub fn run(config: &Config, export: &Export) -> Result<(), Box<dyn Error>> {
+    #[cfg(feature = "sqlite")]
+    let history = config
+        .sqlite
+        .as_deref()
+        .map(|path| sqlite::history(path, export))
+        .transpose()?;
+    #[cfg(not(feature = "sqlite"))]
+    let history = None;
+    let rows = if let Some(rows) = history {
+        rows
+    } else if let Some(path) = &config.state_file {
+        from_state_file(path, export)
+    } else {
+        return Err("Nothing to export without sqlite or state_file in the config".into());
+    };
+
+    let mut out = std::io::stdout().lock();
+    match export.format {
+        ExportFormat::Csv => {
+            writeln!(out, "timestamp,sensor,metric,value")?;
+            for row in rows {
+                writeln!(
+                    out,
+                    "{},{},{},{}",
+                    row.timestamp, row.sensor, row.metric, row.value
+                )?;
+            }
+        }
+        ExportFormat::Json => {
+            serde_json::to_writer(&mut out, &rows)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn from_state_file(path: &std::path::Path, export: &Export) -> Vec<Row> {
+    let mut rows: Vec<Row> = checkpoint::load(path)
+        .into_iter()
+        .filter(|(sensor, _)| export.sensor.as_ref().is_none_or(|id| id == sensor))
+        .flat_map(|(sensor, state)| {
+            state
+                .samples
+                .into_iter()
+                .flat_map(move |(metric, samples)| {
+                    let sensor = sensor.clone();
+                    samples.into_iter().map(move |(at, value)| Row {
+                        sensor: sensor.clone(),
+                        metric: metric.clone(),
+                        value,
+                        timestamp: timestamp::format(
+                            UNIX_EPOCH + Duration::try_from_secs_f64(at).unwrap_or_default(),
+                        ),
+                    })
+                })
+        })
+        .filter(|row| {
+            let timestamp = row.timestamp.as_str();
+            export.from.as_deref().is_none_or(|from| timestamp >= from)
+                && export.to.as_deref().is_none_or(|to| timestamp < to)
+        })
+        .collect();
+    rows.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    rows
+}
@@ -0,0 +1,206 @@
+use crate::psychrometrics::{self, DewpointFormula, Magnus};
+use evalexpr::{
+    ContextWithMutableFunctions, ContextWithMutableVariables, EvalexprResult, Function,
+    HashMapContext, Node, Value,
+};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// A custom value calculated from a sensor's reading
+#[derive(Clone, Deserialize)]
+pub struct Expression {
+    /// Name used for `{metric}` in topic templates
+    pub name: String,
+    /// evalexpr expression, e.g. `t_f - dewpoint_f`
+    pub value: String,
+    /// Human readable name, defaulting to `name`
+    pub label: Option<String>,
+    pub unit_of_measurement: Option<String>,
+    pub device_class: Option<String>,
+    /// Decimal places published
+    #[serde(default = "default_precision")]
+    pub precision: usize,
+}
+
+impl Expression {
+    pub fn compile(&self) -> Result<Node, String> {
+        evalexpr::build_operator_tree(&self.value)
+            .map_err(|e| format!("Expression {} is invalid: {e}", self.name))
+    }
+
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// Calibrated inputs (°C, %, hPa) and other numeric fields of the sensor's payload available to
+/// expressions
+pub struct Variables<'a> {
+    pub temperature: f64,
+    pub humidity: f64,
+    pub pressure: f64,
+    pub dewpoint: f64,
+    pub fields: &'a BTreeMap<String, f64>,
+}
+
+/// Builds the context expressions are evaluated in. Payload fields are available by name,
+/// alongside `t`, `t_f`, `rh`, `p`, `dewpoint`, `dewpoint_f`, and functions of temperature (°C)
+/// and humidity.
+pub fn context(
+    variables: &Variables,
+    formula: DewpointFormula,
+    magnus: Magnus,
+) -> EvalexprResult<HashMapContext> {
+    let mut context = HashMapContext::new();
+    for (field, value) in variables.fields {
+        context.set_value(field.clone(), Value::Float(*value))?;
+    }
+
+    let fahrenheit = |celsius: f64| celsius.mul_add(1.8, 32.0);
+    for (name, value) in [
+        ("t", variables.temperature),
+        ("t_f", fahrenheit(variables.temperature)),
+        ("rh", variables.humidity),
+        ("p", variables.pressure),
+        ("dewpoint", variables.dewpoint),
+        ("dewpoint_f", fahrenheit(variables.dewpoint)),
+    ] {
+        context.set_value(name.to_string(), Value::Float(value))?;
+    }
+
+    context.set_function(
+        String::from("dewpoint"),
+        binary(move |t, rh| formula.dewpoint(magnus, t, rh)),
+    )?;
+    context.set_function(
+        String::from("heat_index"),
+        binary(psychrometrics::heat_index),
+    )?;
+    for (name, function) in [
+        (
+            "frost_point",
+            psychrometrics::frost_point as fn(Magnus, f64, f64) -> f64,
+        ),
+        ("absolute_humidity", psychrometrics::absolute_humidity),
+        ("vapor_pressure", psychrometrics::vapor_pressure),
+        ("vpd", psychrometrics::vapor_pressure_deficit),
+    ] {
+        context.set_function(
+            name.to_string(),
+            binary(move |t, rh| function(magnus, t, rh)),
+        )?;
+    }
+    context.set_function(
+        String::from("saturation_vapor_pressure"),
+        unary(move |t| psychrometrics::saturation_vapor_pressure(magnus, t)),
+    )?;
+    context.set_function(String::from("c_to_f"), unary(fahrenheit))?;
+    context.set_function(
+        String::from("f_to_c"),
+        unary(|fahrenheit| (fahrenheit - 32.0) / 1.8),
+    )?;
+
+    Ok(context)
+}
+
+fn unary(function: impl Fn(f64) -> f64 + Clone + Send + Sync + 'static) -> Function {
+    Function::new(move |argument| Ok(Value::Float(function(argument.as_number()?))))
+}
+
+fn binary(function: impl Fn(f64, f64) -> f64 + Clone + Send + Sync + 'static) -> Function {
+    Function::new(move |argument| {
+        let arguments = argument.as_fixed_len_tuple(2)?;
+        Ok(Value::Float(function(
+            arguments[0].as_number()?,
+            arguments[1].as_number()?,
+        )))
+    })
+}
+
+const fn default_precision() -> usize {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(expression: &str) -> f64 {
+        let fields = BTreeMap::from([
+            (String::from("battery"), 87.0),
+            (String::from("t"), 99.0),
+            (String::from("rh"), 10.0),
+        ]);
+        let magnus = Magnus::default();
+        let variables = Variables {
+            temperature: 21.5,
+            humidity: 45.0,
+            pressure: 1013.25,
+            dewpoint: DewpointFormula::default().dewpoint(magnus, 21.5, 45.0),
+            fields: &fields,
+        };
+        let context = context(&variables, DewpointFormula::default(), magnus).unwrap();
+        evalexpr::build_operator_tree(expression)
+            .unwrap()
+            .eval_number_with_context(&context)
+            .unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn variables() {
+        assert_close(evaluate("battery"), 87.0);
+        // calibrated inputs take precedence over payload fields of the same name
+        assert_close(evaluate("t"), 21.5);
+        assert_close(evaluate("rh"), 45.0);
+        assert_close(evaluate("t_f"), 70.7);
+        assert_close(evaluate("p"), 1013.25);
+        assert_close(evaluate("dewpoint_f - c_to_f(dewpoint)"), 0.0);
+    }
+
+    #[test]
+    fn functions() {
+        let magnus = Magnus::default();
+        assert_close(
+            evaluate("dewpoint(t, rh)"),
+            DewpointFormula::default().dewpoint(magnus, 21.5, 45.0),
+        );
+        assert_close(evaluate("dewpoint(t, rh) - dewpoint"), 0.0);
+        assert_close(
+            evaluate("frost_point(-5, 80)"),
+            psychrometrics::frost_point(magnus, -5.0, 80.0),
+        );
+        assert_close(
+            evaluate("vpd(t, rh)"),
+            psychrometrics::vapor_pressure_deficit(magnus, 21.5, 45.0),
+        );
+        assert_close(evaluate("c_to_f(100)"), 212.0);
+        assert_close(evaluate("f_to_c(212)"), 100.0);
+        assert_close(evaluate("f_to_c(c_to_f(t))"), 21.5);
+    }
+
+    #[test]
+    fn wrong_arguments() {
+        let fields = BTreeMap::new();
+        let variables = Variables {
+            temperature: 21.5,
+            humidity: 45.0,
+            pressure: 1013.25,
+            dewpoint: 9.2,
+            fields: &fields,
+        };
+        let context = context(&variables, DewpointFormula::default(), Magnus::default()).unwrap();
+        for expression in ["dewpoint(t)", "c_to_f(t, rh)", "unknown(t)"] {
+            assert!(
+                evalexpr::build_operator_tree(expression)
+                    .unwrap()
+                    .eval_number_with_context(&context)
+                    .is_err(),
+                "{expression}"
+            );
+        }
+    }
+}
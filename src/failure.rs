@@ -0,0 +1,104 @@
+//! What happens when something goes wrong that the daemon can't work around, and the exit codes a
+//! supervisor sees when it gives up
+//!
+//! Startup errors, like a broken config, exit with 1.
+
+use crate::event::Event;
+use log::{error, warn};
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// Connecting to a broker failed, e.g. because it rejected the credentials
+pub const EXIT_CONNECT: i32 = 3;
+/// `max_consecutive_errors` messages in a row couldn't be processed
+pub const EXIT_ERRORS: i32 = 4;
+/// A thread panicked
+pub const EXIT_PANIC: i32 = 5;
+
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Exit with a distinct code so a supervisor can restart or alert
+    #[default]
+    Exit,
+    /// Keep trying forever, reconnecting every `retry_interval` seconds
+    Retry,
+}
+
+/// An unrecoverable condition that exits with `code`
+#[derive(Debug)]
+pub struct Fatal {
+    pub code: i32,
+    pub message: String,
+}
+
+impl fmt::Display for Fatal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Fatal {}
+
+/// Exits the process when any thread panics, instead of leaving it running without that thread
+pub fn exit_on_panic() {
+    std::panic::set_hook(Box::new(|info| {
+        error!("{info}");
+        std::process::exit(EXIT_PANIC);
+    }));
+}
+
+/// Code the process exits with after an error: a `Fatal` error's own, or 1 like any startup error
+#[cfg(windows)]
+pub fn exit_code(e: &(dyn Error + 'static)) -> i32 {
+    e.downcast_ref::<Fatal>().map_or(1, |fatal| fatal.code)
+}
+
+/// Exits with the code of a `Fatal` error, passing any other result through
+pub fn exit(result: Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    if let Some(fatal) = result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<Fatal>())
+    {
+        error!("{fatal}");
+        std::process::exit(fatal.code);
+    }
+    result
+}
+
+/// Calls `connect` until it succeeds with `FailurePolicy::Retry`, or `None` if `rx` receives
+/// `Event::Shutdown` while waiting to try again
+pub fn connect<T>(
+    policy: FailurePolicy,
+    interval: Duration,
+    rx: &Receiver<Event>,
+    addr: &str,
+    mut connect: impl FnMut() -> Result<T, Box<dyn Error>>,
+) -> Result<Option<T>, Box<dyn Error>> {
+    loop {
+        let e = match connect() {
+            Ok(client) => return Ok(Some(client)),
+            Err(e) => e,
+        };
+        if policy == FailurePolicy::Exit {
+            return Err(Fatal {
+                code: EXIT_CONNECT,
+                message: format!("Error connecting to {addr}: {e}"),
+            }
+            .into());
+        }
+        warn!(
+            "Error connecting to {addr}: {e}, retrying in {}s",
+            interval.as_secs()
+        );
+        // nothing but the signal handler sends events before connecting
+        if let Ok(Event::Shutdown) | Err(RecvTimeoutError::Disconnected) = rx.recv_timeout(interval)
+        {
+            return Ok(None);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Smoothing {
+    /// Exponential moving average; `alpha` closer to 0 smooths more
+    Ema { alpha: f64 },
+    /// Mean of the last `window` readings
+    MovingAverage { window: usize },
+}
+
+impl Smoothing {
+    pub fn validate(self) -> Result<(), String> {
+        match self {
+            Self::Ema { alpha } if alpha <= 0.0 || alpha > 1.0 => {
+                Err(format!("EMA alpha must be in (0, 1], not {alpha}"))
+            }
+            Self::MovingAverage { window: 0 } => Err(String::from("Moving average window is 0")),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Smoothing state for a single input
+#[derive(Default)]
+pub struct Smoothed {
+    ema: Option<f64>,
+    window: VecDeque<f64>,
+}
+
+impl Smoothed {
+    /// Adds a reading and returns the smoothed value
+    pub fn update(&mut self, smoothing: Smoothing, value: f64) -> f64 {
+        match smoothing {
+            Smoothing::Ema { alpha } => {
+                let ema = self
+                    .ema
+                    .map_or(value, |ema| alpha.mul_add(value - ema, ema));
+                self.ema = Some(ema);
+                ema
+            }
+            Smoothing::MovingAverage { window } => {
+                self.window.push_back(value);
+                while self.window.len() > window {
+                    self.window.pop_front();
+                }
+                #[allow(clippy::cast_precision_loss)]
+                let len = self.window.len() as f64;
+                self.window.iter().sum::<f64>() / len
+            }
+        }
+    }
+}
+
+/// Lowest plausible relative humidity, since dewpoint is undefined at 0%
+const MIN_HUMIDITY: f64 = 0.1;
+
+/// What to do with readings outside physical bounds
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfRange {
+    #[default]
+    Reject,
+    /// Clamp to the nearest plausible value, unless the reading isn't a number at all
+    Clamp,
+}
+
+/// Why a calibrated reading is outside of `temperature_range` (°C) or possible humidity, if it is
+pub fn out_of_range(
+    temperature: f64,
+    humidity: f64,
+    temperature_range: [f64; 2],
+) -> Option<String> {
+    if !(MIN_HUMIDITY..=100.0).contains(&humidity) {
+        return Some(format!("humidity {humidity}% is impossible"));
+    }
+    if !(temperature_range[0]..=temperature_range[1]).contains(&temperature) {
+        return Some(format!("temperature {temperature}°C is implausible"));
+    }
+    None
+}
+
+/// Clamps a calibrated reading into `temperature_range` (°C) and possible humidity
+pub fn clamp(temperature: f64, humidity: f64, temperature_range: [f64; 2]) -> (f64, f64) {
+    (
+        temperature.clamp(temperature_range[0], temperature_range[1]),
+        humidity.clamp(MIN_HUMIDITY, 100.0),
+    )
+}
+
+/// Consecutive rejected readings after which a jump is accepted as a real change
+const MAX_REJECTED: u32 = 3;
+
+/// Rejects readings jumping implausibly far from the last accepted reading
+#[derive(Default)]
+pub struct SpikeFilter {
+    last: Option<(f64, f64)>,
+    rejected: u32,
+}
+
+impl SpikeFilter {
+    /// Checks a calibrated reading against the optional maximum jumps (°C and % RH) between
+    /// consecutive readings, returning why it was rejected
+    pub fn check(
+        &mut self,
+        temperature: f64,
+        humidity: f64,
+        max_temperature_jump: Option<f64>,
+        max_humidity_jump: Option<f64>,
+    ) -> Result<(), String> {
+        if let Some((last_temperature, last_humidity)) = self.last {
+            let jumped = |value: f64, last: f64, max: Option<f64>| {
+                max.is_some_and(|max| (value - last).abs() > max)
+            };
+            if self.rejected < MAX_REJECTED
+                && (jumped(temperature, last_temperature, max_temperature_jump)
+                    || jumped(humidity, last_humidity, max_humidity_jump))
+            {
+                self.rejected += 1;
+                return Err(format!(
+                    "{temperature}°C / {humidity}% jumped too far from {last_temperature}°C / {last_humidity}%"
+                ));
+            }
+        }
+
+        self.last = Some((temperature, humidity));
+        self.rejected = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_reading_is_accepted() {
+        let mut filter = SpikeFilter::default();
+        assert!(filter.check(20.0, 50.0, Some(1.0), Some(1.0)).is_ok());
+    }
+
+    #[test]
+    fn rejects_jumps() {
+        let mut filter = SpikeFilter::default();
+        filter.check(20.0, 50.0, Some(2.0), Some(5.0)).unwrap();
+        assert!(filter.check(21.5, 53.0, Some(2.0), Some(5.0)).is_ok());
+        assert!(filter.check(30.0, 53.0, Some(2.0), Some(5.0)).is_err());
+        assert!(filter.check(21.5, 60.0, Some(2.0), Some(5.0)).is_err());
+        // compared to the last accepted reading, not the rejected ones
+        assert!(filter.check(22.0, 55.0, Some(2.0), Some(5.0)).is_ok());
+    }
+
+    #[test]
+    fn accepts_a_lasting_change() {
+        let mut filter = SpikeFilter::default();
+        filter.check(20.0, 50.0, Some(2.0), None).unwrap();
+        for _ in 0..MAX_REJECTED {
+            assert!(filter.check(30.0, 50.0, Some(2.0), None).is_err());
+        }
+        assert!(filter.check(30.0, 50.0, Some(2.0), None).is_ok());
+        assert!(filter.check(30.5, 50.0, Some(2.0), None).is_ok());
+    }
+
+    #[test]
+    fn without_limits() {
+        let mut filter = SpikeFilter::default();
+        filter.check(20.0, 50.0, None, None).unwrap();
+        assert!(filter.check(-40.0, 0.1, None, None).is_ok());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config::Group;
+use crate::discovery;
+use crate::event::{Latest, Message, Readings};
+use log::info;
+use serde::Deserialize;
+
+/// How a group combines its sensors' values
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    fn apply(self, values: impl Iterator<Item = f64>) -> f64 {
+        match self {
+            Self::Mean => {
+                let (sum, count) =
+                    values.fold((0.0, 0_u32), |(sum, count), value| (sum + value, count + 1));
+                sum / f64::from(count)
+            }
+            Self::Min => values.fold(f64::INFINITY, f64::min),
+            Self::Max => values.fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+}
+
+// (metric, component) of each published value
+const TEMPERATURE: (&str, &str) = ("temperature", "sensor");
+const HUMIDITY: (&str, &str) = ("humidity", "sensor");
+const DEWPOINT: (&str, &str) = ("dewpoint", "sensor");
+
+/// New state for a group, if any of its sensors have recent readings
+pub fn update(group: &Group, readings: &Readings) -> Vec<Message> {
+    let max_age = group.max_age();
+    let recent: Vec<Latest> = group
+        .sensors
+        .iter()
+        .filter_map(|id| readings.get(id))
+        .filter(|(_, at)| at.elapsed() <= max_age)
+        .map(|&(latest, _)| latest)
+        .collect();
+    if recent.is_empty() {
+        return Vec::new();
+    }
+
+    let aggregate = |value: fn(&Latest) -> f64| group.aggregation.apply(recent.iter().map(value));
+    let unit = group.output_unit;
+    let temperature = unit.convert_celsius(aggregate(|latest| latest.temperature));
+    let humidity = aggregate(|latest| latest.humidity);
+    let dewpoint = unit.convert_celsius(aggregate(|latest| latest.dewpoint));
+
+    info!(
+        "Group {} ({} of {} sensors): {temperature:.2}{} / {humidity:.1}% / dewpoint {dewpoint:.2}{}",
+        group.id,
+        recent.len(),
+        group.sensors.len(),
+        unit.symbol(),
+        unit.symbol()
+    );
+
+    [
+        (TEMPERATURE, temperature),
+        (HUMIDITY, humidity),
+        (DEWPOINT, dewpoint),
+    ]
+    .into_iter()
+    .map(|((metric, component), value)| Message {
+        topic: group.state_topic(metric, component),
+        payload: format!("{value:.1}"),
+        retain: group.retain(),
+        broker: None,
+    })
+    .collect()
+}
+
+/// Topics and payloads of the Home Assistant discovery configs for a group
+pub fn discovery_configs(group: &Group, daemon: Option<&str>) -> Vec<(String, String)> {
+    let unit = group.output_unit.symbol();
+    [
+        (TEMPERATURE, "Temperature", "temperature", unit),
+        (HUMIDITY, "Humidity", "humidity", "%"),
+        (DEWPOINT, "Dewpoint", "temperature", unit),
+    ]
+    .into_iter()
+    .filter_map(|((metric, component), label, device_class, unit)| {
+        let topic = group.discovery_topic(metric, component)?;
+        let payload = serde_json::json!({
+            "name": format!("{} {label}", group.name()),
+            "unique_id": discovery::unique_id(&group.id, metric),
+            "device": discovery::device(&group.id, group.name()),
+            "device_class": device_class,
+            "state_class": "measurement",
+            "state_topic": group.state_topic(metric, component),
+            "unit_of_measurement": unit,
+        });
+        Some((topic, discovery::payload(payload, daemon)))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn group(aggregation: &str, output_unit: &str) -> Group {
+        toml::from_str(&format!(
+            r#"
+            id = "house"
+            sensors = ["attic", "basement", "garage"]
+            aggregation = "{aggregation}"
+            output_unit = "{output_unit}"
+            output_topic = "humidity/{{sensor_id}}/{{metric}}"
+            "#
+        ))
+        .unwrap()
+    }
+
+    /// Readings of the attic and basement, and one of the garage too old to count
+    fn readings() -> Readings {
+        let reading = |temperature, humidity, dewpoint, age| {
+            let latest = Latest {
+                temperature,
+                humidity,
+                dewpoint,
+            };
+            (latest, Instant::now() - Duration::from_secs(age))
+        };
+        Readings::from([
+            (String::from("attic"), reading(24.0, 40.0, 9.6, 10)),
+            (String::from("basement"), reading(16.0, 60.0, 8.2, 10)),
+            (String::from("garage"), reading(5.0, 90.0, 3.5, 3600)),
+        ])
+    }
+
+    fn payloads(messages: &[Message]) -> Vec<(&str, &str)> {
+        messages
+            .iter()
+            .map(|message| (message.topic.as_str(), message.payload.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn aggregations() {
+        let messages = update(&group("mean", "C"), &readings());
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/house/temperature", "20.0"),
+                ("humidity/house/humidity", "50.0"),
+                ("humidity/house/dewpoint", "8.9"),
+            ]
+        );
+        let messages = update(&group("min", "C"), &readings());
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/house/temperature", "16.0"),
+                ("humidity/house/humidity", "40.0"),
+                ("humidity/house/dewpoint", "8.2"),
+            ]
+        );
+        let messages = update(&group("max", "F"), &readings());
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/house/temperature", "75.2"),
+                ("humidity/house/humidity", "60.0"),
+                ("humidity/house/dewpoint", "49.3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_without_recent_readings() {
+        let mut readings = readings();
+        readings.retain(|id, _| id == "garage");
+        assert!(update(&group("mean", "C"), &readings).is_empty());
+        assert!(update(&group("mean", "C"), &Readings::new()).is_empty());
+    }
+}
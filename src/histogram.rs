@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+/// Upper bounds of each bucket in milliseconds, with a last bucket for anything slower
+pub const BOUNDS: [f64; 12] = [
+    1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 10000.0,
+];
+
+/// Durations counted into fixed buckets, like an OpenTelemetry explicit bucket histogram
+#[derive(Clone, Default)]
+pub struct Histogram {
+    pub count: u64,
+    /// Milliseconds
+    pub sum: f64,
+    pub buckets: [u64; BOUNDS.len() + 1],
+}
+
+impl Histogram {
+    pub fn record(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        self.count += 1;
+        self.sum += ms;
+        let bucket = BOUNDS
+            .iter()
+            .position(|&bound| ms <= bound)
+            .unwrap_or(BOUNDS.len());
+        self.buckets[bucket] += 1;
+    }
+
+    /// Summary for the status topic, with buckets keyed by their upper bound
+    pub fn json(&self) -> serde_json::Value {
+        let buckets: serde_json::Map<String, serde_json::Value> = BOUNDS
+            .iter()
+            .map(ToString::to_string)
+            .chain([String::from("+Inf")])
+            .zip(self.buckets.iter().map(|&count| count.into()))
+            .collect();
+        #[allow(clippy::cast_precision_loss)]
+        let mean = (self.count > 0).then(|| self.sum / self.count as f64);
+        serde_json::json!({
+            "count": self.count,
+            "sum_ms": self.sum,
+            "mean_ms": mean,
+            "buckets": buckets,
+        })
+    }
+}
@@ -0,0 +1,95 @@
+//! Sensors announced as a device following the [Homie 4](https://homieiot.github.io/) convention,
+//! with a node per sensor and a property per value
+
+use crate::config::{Homie, Sensor};
+use crate::event::Message;
+
+/// Homie IDs may only have lowercase letters, digits and hyphens
+fn id(s: &str) -> String {
+    s.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9') => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+/// (name in `sink::Record` values, display name, unit, decimal places) of each of a sensor's
+/// properties
+fn properties(sensor: &Sensor) -> Vec<(&'static str, &'static str, &'static str, usize)> {
+    let mut properties = vec![
+        ("temperature", "Temperature", "°C", 1),
+        ("humidity", "Humidity", "%", 1),
+    ];
+    properties.extend(
+        sensor
+            .metrics
+            .iter()
+            .filter(|metric| !metric.is_text())
+            .map(|metric| {
+                let unit = metric.unit_of_measurement(sensor.output_unit);
+                (
+                    metric.name(),
+                    metric.label(),
+                    unit.unwrap_or_default(),
+                    metric.precision(),
+                )
+            }),
+    );
+    properties
+}
+
+/// Topics and payloads of the device, node and property attributes, all retained
+pub fn announcements(homie: &Homie, sensors: &[Sensor]) -> Vec<(String, String)> {
+    let device = format!("{}/{}", homie.base, id(&homie.device));
+    let nodes: Vec<String> = sensors.iter().map(|sensor| id(sensor.id())).collect();
+    let mut announcements = vec![
+        (format!("{device}/$homie"), String::from("4.0")),
+        (format!("{device}/$name"), homie.name().to_string()),
+        (format!("{device}/$extensions"), String::new()),
+        (format!("{device}/$nodes"), nodes.join(",")),
+    ];
+    for (sensor, node) in sensors.iter().zip(&nodes) {
+        let properties = properties(sensor);
+        let ids: Vec<String> = properties.iter().map(|(name, ..)| id(name)).collect();
+        announcements.push((format!("{device}/{node}/$name"), sensor.name().to_string()));
+        announcements.push((format!("{device}/{node}/$type"), String::from("sensor")));
+        announcements.push((format!("{device}/{node}/$properties"), ids.join(",")));
+        for ((_, name, unit, _), property) in properties.iter().zip(&ids) {
+            let topic = format!("{device}/{node}/{property}");
+            announcements.push((format!("{topic}/$name"), name.to_string()));
+            announcements.push((format!("{topic}/$datatype"), String::from("float")));
+            announcements.push((format!("{topic}/$unit"), unit.to_string()));
+        }
+    }
+    // published last, once every attribute is in place
+    announcements.push((format!("{device}/$state"), String::from("ready")));
+    announcements
+}
+
+/// Property values of a sensor's reading
+pub fn update(homie: &Homie, sensor: &Sensor, values: &[(String, f64)]) -> Vec<Message> {
+    let node = format!("{}/{}/{}", homie.base, id(&homie.device), id(sensor.id()));
+    properties(sensor)
+        .into_iter()
+        .filter_map(|(name, _, _, precision)| {
+            let (_, value) = values.iter().find(|(n, _)| n == name)?;
+            Some(Message {
+                topic: format!("{node}/{}", id(name)),
+                payload: format!("{value:.precision$}"),
+                retain: true,
+                broker: None,
+            })
+        })
+        .collect()
+}
+
+/// The device's `$state` once the daemon stops, published on a clean shutdown
+pub fn disconnected(homie: &Homie) -> Message {
+    Message {
+        topic: format!("{}/{}/$state", homie.base, id(&homie.device)),
+        payload: String::from("disconnected"),
+        retain: true,
+        broker: None,
+    }
+}
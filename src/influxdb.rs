@@ -0,0 +1,139 @@
+use crate::config::{self, Sensor};
+use crate::sink::{Batched, Record, Sink};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, UNIX_EPOCH};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Writes readings to an InfluxDB v2 bucket in line protocol, in batches from a background thread
+pub struct InfluxDb {
+    /// Measurement and tags of each sensor's lines, by ID
+    series: HashMap<String, String>,
+    lines: Batched<String>,
+}
+
+impl InfluxDb {
+    pub fn new(config: &config::InfluxDb, sensors: &[Sensor]) -> Self {
+        let series = sensors
+            .iter()
+            .map(|sensor| {
+                let tags = [("sensor", sensor.id())]
+                    .into_iter()
+                    .chain(sensor.tags.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+                (sensor.id().to_string(), series(&config.measurement, tags))
+            })
+            .collect();
+
+        let url = format!("{}/api/v2/write", config.url.trim_end_matches('/'));
+        let config = config.clone();
+        let interval = Duration::from_secs(config.flush_interval);
+        let lines = Batched::new("InfluxDB", config.batch_size, interval, move |lines| {
+            ureq::post(&url)
+                .timeout(TIMEOUT)
+                .query("org", &config.org)
+                .query("bucket", &config.bucket)
+                .query("precision", "ns")
+                .set("Authorization", &format!("Token {}", config.token))
+                .set("Content-Type", "text/plain; charset=utf-8")
+                .send_string(&lines.join("\n"))?;
+            Ok(())
+        });
+        Self { series, lines }
+    }
+}
+
+impl Sink for InfluxDb {
+    fn write(&mut self, record: &Record) {
+        if let Some(line) = self
+            .series
+            .get(&record.sensor_id)
+            .and_then(|series| line(series, record))
+        {
+            self.lines.push(line);
+        }
+    }
+}
+
+/// Measurement and tags starting each of a sensor's lines, leaving out tags with an empty key or
+/// value, which InfluxDB rejects the whole batch for
+fn series<'a>(measurement: &str, tags: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut series = escape(measurement, ", ");
+    for (key, value) in tags {
+        if !key.is_empty() && !value.is_empty() {
+            let _ = write!(series, ",{}={}", escape(key, ",= "), escape(value, ",= "));
+        }
+    }
+    series
+}
+
+/// A record's finite values as a line of `series`, unless it has none
+fn line(series: &str, record: &Record) -> Option<String> {
+    let fields: Vec<String> = record
+        .values
+        .iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(name, value)| format!("{}={value}", escape(name, ",= ")))
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+    let nanos = record
+        .time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    Some(format!("{series} {} {nanos}", fields.join(",")))
+}
+
+/// Backslash escapes `special` characters of a measurement, tag, or field key
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_series() {
+        assert_eq!(
+            series(
+                "climate, indoors",
+                [("sensor", "attic"), ("room name", "a=b,c"), ("floor", "")]
+            ),
+            r"climate\,\ indoors,sensor=attic,room\ name=a\=b\,c"
+        );
+        assert_eq!(series("climate", [("", "attic")]), "climate");
+    }
+
+    #[test]
+    fn lines() {
+        let record = Record {
+            sensor_id: String::from("attic"),
+            time: UNIX_EPOCH + Duration::from_secs(1_706_688_900),
+            values: vec![
+                (String::from("dewpoint"), 9.25),
+                (String::from("heat index"), f64::NAN),
+                (String::from("a=b,c d"), 1.0),
+            ],
+        };
+        assert_eq!(
+            line("climate,sensor=attic", &record).as_deref(),
+            Some(r"climate,sensor=attic dewpoint=9.25,a\=b\,c\ d=1 1706688900000000000")
+        );
+
+        let record = Record {
+            values: vec![(String::from("dewpoint"), f64::INFINITY)],
+            ..record
+        };
+        assert_eq!(line("climate,sensor=attic", &record), None);
+    }
+}
@@ -0,0 +1,53 @@
+use crate::timestamp;
+use log::kv::Key;
+use log::Record;
+use std::io::Write;
+
+/// How log records are written to stderr
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// env_logger's human readable lines
+    #[default]
+    Text,
+    /// One JSON object per record, for log shippers
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format {s}, expected text or json")),
+        }
+    }
+}
+
+/// Logs at the info level unless `RUST_LOG` says otherwise
+pub fn init(format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json(record)));
+    }
+    builder.init();
+}
+
+/// A record as JSON, including the MQTT topic it's about if it has one
+fn json(record: &Record) -> serde_json::Value {
+    let mut object = serde_json::json!({
+        "timestamp": timestamp::now(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    });
+    if let (Some(object), Some(topic)) = (
+        object.as_object_mut(),
+        record.key_values().get(Key::from_str("topic")),
+    ) {
+        object.insert(String::from("topic"), topic.to_string().into());
+    }
+    object
+}
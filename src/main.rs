@@ -1,123 +1,591 @@
-use mqtt::client::Client;
-use serde::Deserialize;
+mod api;
+mod args;
+mod bridge;
+mod checkpoint;
+mod client_manager;
+mod compression;
+mod config;
+mod csv_log;
+#[cfg(unix)]
+mod daemon;
+mod diagnostics;
+mod discovery;
+mod encoding;
+mod encryption;
+mod event;
+mod export;
+#[cfg(feature = "expressions")]
+mod expression;
+mod failure;
+mod filter;
+mod group;
+mod histogram;
+mod homie;
+#[cfg(feature = "influxdb")]
+mod influxdb;
+mod logging;
+mod metric;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "postgres")]
+mod postgresql;
+mod preset;
+mod psychrometrics;
+mod pubsub;
+mod rate_limit;
+mod replay;
+mod retention;
+#[cfg(feature = "script")]
+mod script;
+mod sensor;
+#[cfg(windows)]
+mod service;
+mod simulate;
+mod sink;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod statistics;
+mod timestamp;
+mod topic;
+mod trace;
+mod unit;
+mod ventilation;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use args::{Args, Command};
+use client_manager::ClientManager;
+use config::Config;
+use event::{Event, Message, Readings, Sender};
+use failure::{FailurePolicy, Fatal};
+use log::{debug, info, warn};
+use std::collections::HashMap;
 use std::error::Error;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 extern crate ctrlc;
 
-const A: f64 = 17.625;
-const B: f64 = 243.04;
+/// Time shutdown waits for sinks to write their last batches and other threads to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 fn main() -> Result<(), Box<dyn Error>> {
     // config init
-    let args: Vec<String> = std::env::args().collect();
-    let filename = if args.len() >= 2 {
-        &args[1]
-    } else {
-        "config.toml"
-    };
+    let args = Args::parse()?;
+    logging::init(args.log_format);
+    failure::exit_on_panic();
+    if let Some(Command::Export(export)) = &args.command {
+        let config = Config::load(&args.config, None)?;
+        return export::run(&config, export);
+    }
+    #[cfg(windows)]
+    if let Some(command) = args.service {
+        return service::handle(command, &args);
+    }
+    #[cfg(not(windows))]
+    if args.service.is_some() {
+        return Err("Windows services are only supported on Windows".into());
+    }
 
-    let config = std::fs::read_to_string(filename)?
-        .parse::<toml::Value>()
-        .unwrap();
+    if args.daemon {
+        // before any threads are started, which don't survive forking
+        #[cfg(unix)]
+        daemon::start(&args)?;
+        #[cfg(not(unix))]
+        return Err("--daemon is only supported on Unix".into());
+    }
 
-    let broker_addr = config["broker_addr"].as_str().unwrap();
+    let (tx, rx) = mpsc::channel();
+    let shutdown = tx.clone();
+    // with the termination feature this also handles SIGTERM and SIGHUP, e.g. from systemctl stop
+    ctrlc::set_handler(move || {
+        info!("Shutting down");
+        let _ = shutdown.send(Event::Shutdown);
+    })
+    .expect("Error setting signal handler");
 
-    let client_id = config["client_id"].as_str().unwrap();
-    if client_id.len() > 0xFF {
-        panic!("Client ID too long");
+    match &args.command {
+        Some(Command::Publish {
+            topic,
+            payload,
+            retain,
+        }) => {
+            let config = load_config(&args)?;
+            pubsub::publish(&config, topic, payload, *retain, args.trace_packets)
+        }
+        Some(Command::Subscribe { topic }) => {
+            let config = load_config(&args)?;
+            pubsub::subscribe(&config, topic, args.trace_packets, &rx)
+        }
+        _ => failure::exit(run(args, tx, rx)),
     }
+}
 
-    let username = config["username"].as_str().unwrap();
-    if username.len() > 0xFF {
-        panic!("Username too long");
+/// Reads the config with the command line's overrides
+fn load_config(args: &Args) -> Result<Config, Box<dyn Error>> {
+    let mut config = Config::load(&args.config, args.discovery_prefix.clone())?;
+    if let Some(broker_addr) = &args.broker {
+        config.broker.broker_addr.clone_from(broker_addr);
     }
-
-    let password = config["password"].as_str().unwrap();
-    if password.len() > 0xFF {
-        panic!("Password too long");
+    if let Some(username) = &args.username {
+        config.broker.username.clone_from(username);
     }
+    if let Some(client_id) = &args.client_id {
+        config.broker.client_id.clone_from(client_id);
+    }
+    Ok(config)
+}
 
-    let mut client = Client::new(client_id, username, password, 60);
-    client.connect(broker_addr)?;
-
-    client
-        .subscribe(
-            "zigbee2mqtt/tempSensor",
-            calculate_dewpoint("homeassistant/sensor/0x00158d0002c9119d/dewpoint/state"),
-        )
-        .unwrap();
-    client
-        .subscribe(
-            "zigbee2mqtt/0x00158d00069afcf8",
-            calculate_dewpoint("homeassistant/sensor/0x00158d00069afcf8/dewpoint/state"),
-        )
-        .unwrap();
-    client
-        .subscribe(
-            "zigbee2mqtt/0x00158d000802e28e",
-            calculate_dewpoint("homeassistant/sensor/0x00158d000802e28e/dewpoint/state"),
-        )
-        .unwrap();
-
-    client.publish("homeassistant/sensor/dewpoint/config", r#"{"name":"dewpoint","device_class":"temperature","state_topic":"homeassistant/sensor/dewpoint/state","unit_of_measurement":"°F"}"#, true);
-    client.publish("homeassistant/sensor/upstairsDewpoint/config", r#"{"name":"upstairsDewpoint","device_class":"temperature","state_topic":"homeassistant/sensor/upstairsDewpoint/state","unit_of_measurement":"°F"}"#, true);
+/// Connects to the configured brokers and publishes until `rx` receives `Event::Shutdown`
+fn run(args: Args, tx: Sender, rx: Receiver<Event>) -> Result<(), Box<dyn Error>> {
+    let mut config = load_config(&args)?;
 
-    let main_thread = thread::current();
-    let closing = Arc::new(AtomicBool::new(false));
-    let closing_clone = closing.clone();
+    let trace = args.trace_packets;
+    let dry_run = matches!(args.command, Some(Command::Replay { publish: false, .. }));
+    let mut clients = if dry_run {
+        config.dry_run();
+        ClientManager::dry_run(&config, trace)?
+    } else {
+        let Some(clients) = ClientManager::connect(&config, &rx, trace)? else {
+            return Ok(());
+        };
+        clients
+    };
 
-    ctrlc::set_handler(move || {
-        closing_clone.store(true, Ordering::SeqCst);
-        main_thread.unpark();
-    })
-    .expect("Error setting Ctrl-C handler");
+    let daemon_availability = config.daemon_availability_topic.as_deref();
+    let mut configs = Vec::new();
+    for sensor in &config.sensors {
+        configs.extend(sensor::discovery_configs(sensor, daemon_availability));
+    }
+    for ventilation in &config.ventilations {
+        configs.extend(ventilation::discovery_configs(
+            ventilation,
+            daemon_availability,
+        ));
+    }
+    for group in &config.groups {
+        configs.extend(group::discovery_configs(group, daemon_availability));
+    }
+    if let Some(diagnostics) = &config.diagnostics {
+        configs.extend(diagnostics::discovery_configs(
+            diagnostics,
+            daemon_availability,
+        ));
+    }
 
-    while !closing.load(Ordering::SeqCst) {
-        thread::park();
+    // configs of sensors removed since the last run, or every config when cleaning up
+    let current: Vec<&str> = if args.cleanup {
+        Vec::new()
+    } else {
+        configs.iter().map(|(topic, _)| topic.as_str()).collect()
+    };
+    let mut stale = match &config.discovery_cache {
+        Some(cache) => discovery::removed(cache, &current)?,
+        None => Vec::new(),
+    };
+    if args.cleanup {
+        stale.extend(configs.iter().map(|(topic, _)| topic.clone()));
+        stale.extend(daemon_availability.map(String::from));
+        stale.sort_unstable();
+        stale.dedup();
+    }
+    for topic in &stale {
+        info!(topic = topic.as_str(); "Removing {topic}");
+        clients.publish(None, topic, "", true);
+    }
+    if args.cleanup {
+        clients.disconnect();
+        return Ok(());
     }
 
-    client.disconnect();
+    // the mqtt client has no last will support, so offline is only published on a clean shutdown
+    let mut announcements = Vec::new();
+    if let Some(topic) = daemon_availability {
+        announcements.push((topic.to_string(), String::from("online")));
+    }
+    match &config.topic_prefix {
+        Some(prefix) => announcements.extend(
+            configs
+                .into_iter()
+                .map(|(topic, payload)| (topic, discovery::prefix_topics(&payload, prefix))),
+        ),
+        None => announcements.extend(configs),
+    }
+    if let Some(homie) = &config.homie {
+        announcements.extend(homie::announcements(homie, &config.sensors));
+    }
+    let _ = tx.send(Event::Announce);
 
-    Ok(())
-}
+    let mut restored = config
+        .state_file
+        .as_deref()
+        .map(checkpoint::load)
+        .unwrap_or_default();
+    let mut handles = Vec::new();
+    let mut sources = Vec::new();
+    let mut commands = Vec::new();
+    #[cfg(feature = "otlp")]
+    let spans = config.otlp.is_some();
+    #[cfg(not(feature = "otlp"))]
+    let spans = false;
+    for sensor in &config.sensors {
+        let state = restored.remove(sensor.id());
+        let subscriptions = sensor::handlers(sensor, &tx, spans, args.simulate.is_some(), state)?;
+        sources.extend(subscriptions.sources);
+        commands.extend(subscriptions.commands);
+        handles.push((sensor.id().to_string(), subscriptions.handle));
+    }
+    if let Some(wait) = config.warm_start() {
+        // the broker sends retained state right after subscribing, so it's in before any readings
+        let until = Instant::now() + wait;
+        for (_, handle) in &handles {
+            for (topic, handler) in handle.warm_start(until) {
+                clients.subscribe_output(&topic, handler)?;
+            }
+        }
+        thread::sleep(wait);
+    }
+    if let Some(seconds) = args.simulate {
+        simulate::start(&config.sensors, &mut sources, Duration::from_secs(seconds));
+    }
+    bridge::add(&config.bridges, config.retain, &tx, &mut sources);
+    if let Some(Command::Replay { file, speed, .. }) = &args.command {
+        let sources = std::mem::take(&mut sources);
+        replay::start(Path::new(file), *speed, sources, tx.clone())?;
+    }
+    for (topic, handler) in sources {
+        clients.subscribe_source(&topic, handler)?;
+    }
+    for (topic, handler) in commands {
+        clients.subscribe_output(&topic, handler)?;
+    }
+    if let Some(topic) = config.ha_status_topic() {
+        let tx = tx.clone();
+        let handler = Box::new(move |payload: Vec<u8>| {
+            if payload == b"online" {
+                let _ = tx.send(Event::Announce);
+            }
+            None
+        });
+        clients.subscribe_output(&topic, handler)?;
+    }
 
-fn calculate_dewpoint(topic: &'static str) -> Box<dyn Fn(Vec<u8>) -> Option<Vec<u8>> + Send> {
-    Box::new(move |payload| {
-        let r: SensorRecord = serde_json::from_str(
-            &String::from_utf8(payload).expect("Error generating string from payload"),
-        )
-        .expect("Error parsing JSON from string");
+    if let Some(diagnostics) = &config.diagnostics {
+        let tx = tx.clone();
+        let interval = diagnostics.interval();
+        thread::spawn(move || {
+            while tx.send(Event::Diagnostics).is_ok() {
+                thread::sleep(interval);
+            }
+        });
+    }
 
-        println!(
-            "Temp: {:.2}°C / {:.2}°F - Hum: {}%",
-            r.temperature,
-            r.temperature.mul_add(1.8, 32_f64),
-            r.humidity
-        );
+    if config.status_topic.is_some() {
+        let tx = tx.clone();
+        let interval = config.status_interval();
+        thread::spawn(move || {
+            while tx.send(Event::Status).is_ok() {
+                thread::sleep(interval);
+            }
+        });
+    }
+    if config.state_file.is_some() {
+        let tx = tx.clone();
+        let interval = config.checkpoint_interval();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if tx.send(Event::Checkpoint).is_err() {
+                break;
+            }
+        });
+    }
+    #[cfg(feature = "otlp")]
+    if let Some(otlp) = &config.otlp {
+        let tx = tx.clone();
+        let interval = otlp.interval();
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if tx.send(Event::Export).is_err() {
+                break;
+            }
+        });
+    }
 
-        let rh = r.humidity / 100.0;
-        let t = r.temperature;
-        let c = (A * t) / (B + t);
-        let ln_rh = rh.ln();
+    let mut sinks = sink::open(&config)?;
+    retention::start(&config);
+    let mut readings = Readings::new();
+    let mut stats = diagnostics::Stats::new();
+    #[cfg(feature = "otlp")]
+    let started = SystemTime::now();
+    #[cfg(feature = "otlp")]
+    let mut spans = Vec::new();
+    // OTLP exports still posting
+    #[cfg(feature = "otlp")]
+    let mut exports: Vec<thread::JoinHandle<()>> = Vec::new();
+    // last message published to each topic, by broker
+    let mut states: HashMap<(Option<String>, String), Message> = HashMap::new();
+    let mut consecutive_errors = 0;
+    let mut fatal = None;
+    let mut error_limit =
+        rate_limit::TokenBucket::new(config.errors_per_minute, Duration::from_secs(60));
+    let mut suppressed_errors = 0;
+    let mut publish_limit = rate_limit::PublishLimit::new(
+        config.publishes_per_minute,
+        config.publishes_per_topic_per_minute,
+    );
+    let save_state = || {
+        if let Some(path) = &config.state_file {
+            let state = handles
+                .iter()
+                .map(|(id, handle)| (id.clone(), handle.checkpoint()))
+                .collect();
+            if let Err(e) = checkpoint::save(path, &state) {
+                warn!("Error saving state to {}: {e}", path.display());
+            }
+        }
+    };
+    for event in &rx {
+        // neither is state to replay when Home Assistant restarts
+        let replayable = !matches!(event, Event::Announce | Event::Error { .. });
+        // discovery configs are a bounded burst that Home Assistant can't do without
+        let limited = !matches!(event, Event::Announce);
+        let messages = match event {
+            Event::Publish(message) => vec![message],
+            Event::Reading {
+                sensor_id,
+                latest,
+                received,
+                values,
+            } => {
+                let record = sink::Record {
+                    sensor_id: sensor_id.clone(),
+                    time: SystemTime::now(),
+                    values,
+                };
+                for sink in &mut sinks {
+                    sink.write(&record);
+                }
+                stats.readings += 1;
+                consecutive_errors = 0;
+                // everything calculated from the reading was queued before it
+                stats
+                    .latency
+                    .entry(sensor_id.clone())
+                    .or_default()
+                    .record(received.elapsed());
+                readings.insert(sensor_id.clone(), (latest, Instant::now()));
+                let ventilations = config
+                    .ventilations
+                    .iter()
+                    .filter(|v| v.indoor == sensor_id || v.outdoor == sensor_id)
+                    .flat_map(|v| ventilation::update(v, &readings));
+                let groups = config
+                    .groups
+                    .iter()
+                    .filter(|g| g.sensors.contains(&sensor_id))
+                    .flat_map(|g| group::update(g, &readings));
+                let homie = config.homie.as_ref().and_then(|homie| {
+                    let sensor = config.sensors.iter().find(|s| s.id() == sensor_id)?;
+                    Some(homie::update(homie, sensor, &record.values))
+                });
+                ventilations
+                    .chain(groups)
+                    .chain(homie.into_iter().flatten())
+                    .collect()
+            }
+            Event::Announce => {
+                let discovery = announcements.iter().map(|(topic, payload)| Message {
+                    topic: topic.clone(),
+                    payload: payload.clone(),
+                    retain: true,
+                    broker: None,
+                });
+                discovery.chain(states.values().cloned()).collect()
+            }
+            Event::Received { sensor_id } => {
+                stats.messages += 1;
+                stats.last_seen.insert(sensor_id, SystemTime::now());
+                continue;
+            }
+            Event::Handled {
+                sensor_id,
+                duration,
+            } => {
+                stats
+                    .handler_time
+                    .entry(sensor_id)
+                    .or_default()
+                    .record(duration);
+                continue;
+            }
+            Event::Error {
+                sensor_id,
+                topic,
+                message,
+            } => {
+                stats.errors += 1;
+                stats.last_error = Some(message.clone());
+                consecutive_errors += 1;
+                if config.max_consecutive_errors == Some(consecutive_errors) {
+                    let message = format!("{consecutive_errors} consecutive errors");
+                    if config.on_failure == FailurePolicy::Exit {
+                        fatal = Some(Fatal {
+                            code: failure::EXIT_ERRORS,
+                            message,
+                        });
+                        break;
+                    }
+                    warn!("{message}, carrying on");
+                }
+                let Some(error_topic) = &config.error_topic else {
+                    continue;
+                };
+                if !error_limit.take() {
+                    suppressed_errors += 1;
+                    continue;
+                }
+                let payload = diagnostics::error(&sensor_id, topic, &message, suppressed_errors);
+                suppressed_errors = 0;
+                vec![Message {
+                    topic: error_topic.clone(),
+                    payload,
+                    retain: false,
+                    broker: None,
+                }]
+            }
+            Event::Diagnostics => config
+                .diagnostics
+                .as_ref()
+                .map(|diagnostics| diagnostics::update(diagnostics, &stats))
+                .unwrap_or_default(),
+            Event::Status => config
+                .status_topic
+                .iter()
+                .map(|topic| Message {
+                    topic: topic.clone(),
+                    payload: diagnostics::status(&stats, &config.broker.broker_addr),
+                    retain: true,
+                    broker: None,
+                })
+                .collect(),
+            #[cfg(feature = "otlp")]
+            Event::Span(span) => {
+                spans.push(span);
+                continue;
+            }
+            #[cfg(feature = "otlp")]
+            Event::Export => {
+                if let Some(otlp) = &config.otlp {
+                    let counters = [
+                        otlp::Counter {
+                            name: "mqtt_dewpoint.messages",
+                            description: "Messages received on sensors' source topics",
+                            value: stats.messages,
+                        },
+                        otlp::Counter {
+                            name: "mqtt_dewpoint.readings",
+                            description: "Readings calculated",
+                            value: stats.readings,
+                        },
+                        otlp::Counter {
+                            name: "mqtt_dewpoint.published",
+                            description: "Messages published",
+                            value: stats.published,
+                        },
+                        otlp::Counter {
+                            name: "mqtt_dewpoint.published.bytes",
+                            description: "Bytes of payload published",
+                            value: stats.published_bytes,
+                        },
+                        otlp::Counter {
+                            name: "mqtt_dewpoint.errors",
+                            description: "Errors processing messages",
+                            value: stats.errors,
+                        },
+                    ];
+                    let histograms = [
+                        otlp::Histograms {
+                            name: "mqtt_dewpoint.handler.duration",
+                            description: "Time sensors' handlers take with a message",
+                            histograms: stats.handler_time.clone().into_iter().collect(),
+                        },
+                        otlp::Histograms {
+                            name: "mqtt_dewpoint.latency",
+                            description: "Time from a message arriving to publishing its values",
+                            histograms: stats.latency.clone().into_iter().collect(),
+                        },
+                    ];
+                    // posting can take a while, which shouldn't hold up publishing
+                    let otlp = otlp.clone();
+                    let spans = std::mem::take(&mut spans);
+                    exports.retain(|export| !export.is_finished());
+                    exports.push(thread::spawn(move || {
+                        otlp::export(&otlp, started, &counters, &histograms, &spans);
+                    }));
+                }
+                continue;
+            }
+            Event::Checkpoint => {
+                save_state();
+                continue;
+            }
+            Event::Shutdown => break,
+        };
 
-        let dewpoint = (B * (ln_rh + c)) / (A - ln_rh - c);
-        let dewpoint_f = dewpoint.mul_add(1.8, 32_f64);
+        for message in messages {
+            if limited && !publish_limit.take(&message.topic) {
+                debug!(
+                    target: "mqtt::client",
+                    "Rate limit dropped message to {}",
+                    message.topic
+                );
+                stats.rate_limited += 1;
+                continue;
+            }
+            clients.publish(
+                message.broker.as_deref(),
+                &message.topic,
+                &message.payload,
+                message.retain,
+            );
+            stats.published += 1;
+            stats.published_bytes += message.payload.len() as u64;
+            *stats
+                .published_topics
+                .entry(message.topic.clone())
+                .or_default() += 1;
+            if replayable {
+                states.insert((message.broker.clone(), message.topic.clone()), message);
+            }
+        }
+    }
 
-        println!("Dewpoint: {:.2}°C / {:.2}°F", dewpoint, dewpoint_f);
+    save_state();
+    if let Some(diagnostics) = &config.diagnostics {
+        let message = diagnostics::disconnected(diagnostics);
+        clients.publish(None, &message.topic, &message.payload, message.retain);
+    }
+    if let Some(homie) = &config.homie {
+        let message = homie::disconnected(homie);
+        clients.publish(None, &message.topic, &message.payload, message.retain);
+    }
+    if let Some(topic) = daemon_availability {
+        clients.publish(None, topic, "offline", true);
+    }
+    clients.disconnect();
 
-        Some(mqtt::message::make_publish(
-            topic,
-            &format!("{:.1}", dewpoint_f),
-            false,
-        ))
-    })
-}
+    // sinks write what they have left when dropped
+    let mut threads = vec![("sinks", thread::spawn(move || drop(sinks)))];
+    threads.extend(
+        handles
+            .into_iter()
+            .filter_map(|(_, handle)| Some(("sensor timers", handle.stop()?))),
+    );
+    #[cfg(feature = "otlp")]
+    threads.extend(exports.into_iter().map(|export| ("OTLP export", export)));
+    sink::join(threads, Instant::now() + SHUTDOWN_TIMEOUT);
 
-#[derive(Deserialize)]
-struct SensorRecord {
-    temperature: f64,
-    humidity: f64,
+    fatal.map_or(Ok(()), |fatal| Err(fatal.into()))
 }
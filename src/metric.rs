@@ -0,0 +1,243 @@
+use crate::config::Sensor;
+use crate::psychrometrics;
+use crate::unit::Unit;
+use serde::Deserialize;
+
+/// Calibrated inputs for a metric calculation
+pub struct Reading {
+    /// °C
+    pub temperature: f64,
+    /// Relative humidity, %
+    pub humidity: f64,
+    /// hPa
+    pub pressure: f64,
+    /// Latest temperature (°C) from the sensor's `surface_temperature_topic`
+    pub surface_temperature: Option<f64>,
+    /// °C per hour over the sensor's `trend_window`
+    pub temperature_trend: Option<f64>,
+    /// °C per hour over the sensor's `trend_window`
+    pub dewpoint_trend: Option<f64>,
+}
+
+const TENDENCIES: [&str; 3] = ["rising", "falling", "steady"];
+
+/// A value derived from a sensor's temperature and humidity
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Dewpoint,
+    FrostPoint,
+    HeatIndex,
+    Humidex,
+    AbsoluteHumidity,
+    #[serde(rename = "vpd")]
+    VaporPressureDeficit,
+    Enthalpy,
+    MixingRatio,
+    SpecificHumidity,
+    DewpointDepression,
+    WetBulb,
+    /// Comfort level text based on dewpoint
+    Comfort,
+    /// Relative humidity at the sensor's surface temperature
+    CondensationRisk,
+    /// Whether condensation risk is above the sensor's threshold
+    Condensation,
+    /// Rate of change in temperature per hour
+    TemperatureTrend,
+    /// Rate of change in dewpoint per hour
+    DewpointTrend,
+    /// Whether dewpoint is rising, falling, or steady
+    DewpointTendency,
+}
+
+impl Metric {
+    /// Name used for `{metric}` in topic templates
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Dewpoint => "dewpoint",
+            Self::FrostPoint => "frost_point",
+            Self::HeatIndex => "heat_index",
+            Self::Humidex => "humidex",
+            Self::AbsoluteHumidity => "absolute_humidity",
+            Self::VaporPressureDeficit => "vpd",
+            Self::Enthalpy => "enthalpy",
+            Self::MixingRatio => "mixing_ratio",
+            Self::SpecificHumidity => "specific_humidity",
+            Self::DewpointDepression => "dewpoint_depression",
+            Self::WetBulb => "wet_bulb",
+            Self::Comfort => "comfort",
+            Self::CondensationRisk => "condensation_risk",
+            Self::Condensation => "condensation",
+            Self::TemperatureTrend => "temperature_trend",
+            Self::DewpointTrend => "dewpoint_trend",
+            Self::DewpointTendency => "dewpoint_tendency",
+        }
+    }
+
+    /// Human readable name, appended to the sensor name for Home Assistant entities
+    pub const fn label(self) -> &'static str {
+        match self {
+            Self::Dewpoint => "Dewpoint",
+            Self::FrostPoint => "Frost point",
+            Self::HeatIndex => "Heat index",
+            Self::Humidex => "Humidex",
+            Self::AbsoluteHumidity => "Absolute humidity",
+            Self::VaporPressureDeficit => "VPD",
+            Self::Enthalpy => "Enthalpy",
+            Self::MixingRatio => "Mixing ratio",
+            Self::SpecificHumidity => "Specific humidity",
+            Self::DewpointDepression => "Dewpoint depression",
+            Self::WetBulb => "Wet bulb",
+            Self::Comfort => "Comfort",
+            Self::CondensationRisk => "Condensation risk",
+            Self::Condensation => "Condensation",
+            Self::TemperatureTrend => "Temperature trend",
+            Self::DewpointTrend => "Dewpoint trend",
+            Self::DewpointTendency => "Dewpoint tendency",
+        }
+    }
+
+    /// Calculates the metric from a reading, in the sensor's output unit where the metric is a
+    /// temperature. Comfort is calculated as the dewpoint it's classified from, condensation as its
+    /// risk, and dewpoint tendency as the dewpoint trend.
+    ///
+    /// Returns `None` for metrics based on an input the sensor hasn't received yet.
+    pub fn calculate(self, reading: &Reading, sensor: &Sensor) -> Option<f64> {
+        let &Reading {
+            temperature: t,
+            humidity: rh,
+            pressure: p,
+            surface_temperature,
+            temperature_trend,
+            dewpoint_trend,
+        } = reading;
+        let unit = sensor.output_unit;
+        let magnus = sensor.magnus;
+        let dewpoint = || sensor.dewpoint_formula.dewpoint(magnus, t, rh);
+        let value = match self {
+            Self::Dewpoint | Self::Comfort => unit.convert_celsius(dewpoint()),
+            Self::FrostPoint => unit.convert_celsius(psychrometrics::frost_point(magnus, t, rh)),
+            Self::HeatIndex => unit.convert_celsius(psychrometrics::heat_index(t, rh)),
+            Self::Humidex => psychrometrics::humidex(t, dewpoint()),
+            Self::AbsoluteHumidity => psychrometrics::absolute_humidity(magnus, t, rh),
+            Self::VaporPressureDeficit => psychrometrics::vapor_pressure_deficit(magnus, t, rh),
+            Self::Enthalpy => psychrometrics::enthalpy(magnus, t, rh, p),
+            Self::MixingRatio => psychrometrics::mixing_ratio(magnus, t, rh, p) * 1000.0,
+            Self::SpecificHumidity => psychrometrics::specific_humidity(magnus, t, rh, p) * 1000.0,
+            Self::DewpointDepression => unit.convert_celsius_delta(t - dewpoint()),
+            Self::WetBulb => unit.convert_celsius(psychrometrics::wet_bulb(magnus, t, rh, p)),
+            Self::CondensationRisk | Self::Condensation => {
+                psychrometrics::surface_humidity(magnus, t, rh, surface_temperature?)
+            }
+            Self::TemperatureTrend => unit.convert_celsius_delta(temperature_trend?),
+            Self::DewpointTrend | Self::DewpointTendency => {
+                unit.convert_celsius_delta(dewpoint_trend?)
+            }
+        };
+        Some(value)
+    }
+
+    /// Decimal places published
+    pub const fn precision(self) -> usize {
+        match self {
+            Self::VaporPressureDeficit | Self::TemperatureTrend | Self::DewpointTrend => 2,
+            _ => 1,
+        }
+    }
+
+    /// State payload for a calculated value
+    pub fn payload(self, value: f64, sensor: &Sensor) -> String {
+        match self {
+            Self::Comfort => sensor.comfort_label(value).to_string(),
+            Self::Condensation if value >= sensor.condensation_threshold => String::from("ON"),
+            Self::Condensation => String::from("OFF"),
+            Self::DewpointTendency if value > sensor.steady_threshold => {
+                String::from(TENDENCIES[0])
+            }
+            Self::DewpointTendency if value < -sensor.steady_threshold => {
+                String::from(TENDENCIES[1])
+            }
+            Self::DewpointTendency => String::from(TENDENCIES[2]),
+            _ => format!("{value:.*}", self.precision()),
+        }
+    }
+
+    /// Whether the payload is text rather than the formatted value
+    pub const fn is_text(self) -> bool {
+        matches!(
+            self,
+            Self::Comfort | Self::Condensation | Self::DewpointTendency
+        )
+    }
+
+    /// Possible states of an `enum` sensor
+    pub fn options(self, sensor: &Sensor) -> Option<Vec<&str>> {
+        match self {
+            Self::Comfort => Some(sensor.comfort_labels.iter().map(String::as_str).collect()),
+            Self::DewpointTendency => Some(TENDENCIES.to_vec()),
+            _ => None,
+        }
+    }
+
+    pub const fn unit_of_measurement(self, unit: Unit) -> Option<&'static str> {
+        match self {
+            Self::Dewpoint
+            | Self::FrostPoint
+            | Self::HeatIndex
+            | Self::DewpointDepression
+            | Self::WetBulb => Some(unit.symbol()),
+            Self::Humidex | Self::Comfort | Self::Condensation | Self::DewpointTendency => None,
+            Self::TemperatureTrend | Self::DewpointTrend => Some(unit.rate_symbol()),
+            Self::AbsoluteHumidity => Some("g/m³"),
+            Self::VaporPressureDeficit => Some("kPa"),
+            Self::Enthalpy => Some("kJ/kg"),
+            Self::MixingRatio | Self::SpecificHumidity => Some("g/kg"),
+            Self::CondensationRisk => Some("%"),
+        }
+    }
+
+    pub const fn device_class(self) -> Option<&'static str> {
+        match self {
+            Self::Dewpoint | Self::FrostPoint | Self::HeatIndex | Self::WetBulb => {
+                Some("temperature")
+            }
+            // dewpoint depression is a temperature difference, which Home Assistant would
+            // mangle if it converted it like a temperature
+            Self::Humidex
+            | Self::Enthalpy
+            | Self::MixingRatio
+            | Self::SpecificHumidity
+            | Self::DewpointDepression
+            | Self::TemperatureTrend
+            | Self::DewpointTrend => None,
+            Self::AbsoluteHumidity => Some("absolute_humidity"),
+            Self::VaporPressureDeficit => Some("pressure"),
+            Self::Comfort | Self::DewpointTendency => Some("enum"),
+            Self::CondensationRisk => Some("humidity"),
+            Self::Condensation => Some("moisture"),
+        }
+    }
+
+    /// Icon for metrics without a device class Home Assistant picks one for
+    pub const fn icon(self) -> Option<&'static str> {
+        match self {
+            Self::Humidex => Some("mdi:sun-thermometer"),
+            Self::MixingRatio | Self::SpecificHumidity => Some("mdi:water-percent"),
+            Self::DewpointDepression => Some("mdi:thermometer-water"),
+            Self::Comfort => Some("mdi:emoticon-outline"),
+            Self::TemperatureTrend | Self::DewpointTrend | Self::DewpointTendency => {
+                Some("mdi:chart-line")
+            }
+            _ => None,
+        }
+    }
+
+    /// Home Assistant integration the metric is discovered as, for `{component}` in topic templates
+    pub const fn component(self) -> &'static str {
+        match self {
+            Self::Condensation => "binary_sensor",
+            _ => "sensor",
+        }
+    }
+}
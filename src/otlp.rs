@@ -0,0 +1,172 @@
+//! Minimal OTLP/HTTP export of counters, histograms, and spans, encoded as JSON
+
+use crate::config::Otlp;
+use crate::histogram::{self, Histogram};
+use log::warn;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SCOPE: &str = "mqtt_dewpoint";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A finished span of work
+pub struct Span {
+    pub name: &'static str,
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub parent_id: Option<u64>,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+/// Random, practically unique span or trace ID
+pub fn id() -> u64 {
+    // each RandomState is seeded differently
+    RandomState::new().build_hasher().finish()
+}
+
+/// A monotonic counter since `started`
+pub struct Counter {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub value: u64,
+}
+
+/// Per-sensor distributions of durations in milliseconds since `started`
+pub struct Histograms {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// By sensor ID
+    pub histograms: Vec<(String, Histogram)>,
+}
+
+/// Posts spans, counters, and histograms to the collector, logging failures since there's nothing
+/// else to do about them
+pub fn export(
+    otlp: &Otlp,
+    started: SystemTime,
+    counters: &[Counter],
+    histograms: &[Histograms],
+    spans: &[Span],
+) {
+    let resource = serde_json::json!({
+        "attributes": [attribute("service.name", &otlp.service_name)],
+    });
+
+    let now = nanos(SystemTime::now());
+    let mut metrics: Vec<serde_json::Value> = counters
+        .iter()
+        .map(|counter| {
+            serde_json::json!({
+                "name": counter.name,
+                "description": counter.description,
+                "sum": {
+                    "dataPoints": [{
+                        "asInt": counter.value.to_string(),
+                        "startTimeUnixNano": nanos(started),
+                        "timeUnixNano": now,
+                    }],
+                    // cumulative
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                },
+            })
+        })
+        .collect();
+    metrics.extend(histograms.iter().map(|metric| {
+        let points: Vec<serde_json::Value> = metric
+            .histograms
+            .iter()
+            .map(|(sensor_id, histogram)| {
+                serde_json::json!({
+                    "attributes": [attribute("sensor.id", sensor_id)],
+                    "startTimeUnixNano": nanos(started),
+                    "timeUnixNano": now,
+                    "count": histogram.count.to_string(),
+                    "sum": histogram.sum,
+                    "bucketCounts": histogram
+                        .buckets
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>(),
+                    "explicitBounds": histogram::BOUNDS,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "name": metric.name,
+            "description": metric.description,
+            "unit": "ms",
+            "histogram": {
+                "dataPoints": points,
+                // cumulative
+                "aggregationTemporality": 2,
+            },
+        })
+    }));
+    post(
+        otlp,
+        "v1/metrics",
+        &serde_json::json!({
+            "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{ "scope": { "name": SCOPE }, "metrics": metrics }],
+            }],
+        }),
+    );
+
+    if spans.is_empty() {
+        return;
+    }
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            serde_json::json!({
+                "traceId": format!("{:032x}", span.trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "parentSpanId": span.parent_id.map(|id| format!("{id:016x}")),
+                "name": span.name,
+                // internal
+                "kind": 1,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end),
+                "attributes": span
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| attribute(key, value))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    post(
+        otlp,
+        "v1/traces",
+        &serde_json::json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{ "scope": { "name": SCOPE }, "spans": spans }],
+            }],
+        }),
+    );
+}
+
+fn post(otlp: &Otlp, path: &str, body: &serde_json::Value) {
+    let url = format!("{}/{path}", otlp.endpoint.trim_end_matches('/'));
+    if let Err(e) = ureq::post(&url).timeout(TIMEOUT).send_json(body) {
+        warn!("Error exporting to {url}: {e}");
+    }
+}
+
+fn attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// Nanoseconds since the Unix epoch, as a string since they don't fit in a JSON number
+fn nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
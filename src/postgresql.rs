@@ -0,0 +1,74 @@
+use crate::config;
+use crate::sink::{Batched, Record, Sink};
+use postgres::{Client, NoTls};
+use std::error::Error;
+use std::time::{Duration, SystemTime};
+
+/// Inserts readings into a PostgreSQL (or TimescaleDB) table, in batches from a background thread
+pub struct Postgres {
+    rows: Batched<(String, String, f64, SystemTime)>,
+}
+
+impl Postgres {
+    pub fn new(config: &config::Postgres) -> Self {
+        let config = config.clone();
+        let interval = Duration::from_secs(config.flush_interval);
+        // connected on the first batch, and again after any error
+        let mut client: Option<Client> = None;
+        let rows = Batched::new("PostgreSQL", config.batch_size, interval, move |rows| {
+            let result = insert(&mut client, &config, rows);
+            if result.is_err() {
+                client = None;
+            }
+            result
+        });
+        Self { rows }
+    }
+}
+
+impl Sink for Postgres {
+    fn write(&mut self, record: &Record) {
+        for (metric, value) in &record.values {
+            self.rows.push((
+                record.sensor_id.clone(),
+                metric.clone(),
+                *value,
+                record.time,
+            ));
+        }
+    }
+}
+
+fn insert(
+    client: &mut Option<Client>,
+    config: &config::Postgres,
+    rows: &[(String, String, f64, SystemTime)],
+) -> Result<(), Box<dyn Error>> {
+    let client = match client {
+        Some(client) => client,
+        None => {
+            let mut connected = Client::connect(&config.url, NoTls)?;
+            connected.batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    sensor TEXT NOT NULL,
+                    metric TEXT NOT NULL,
+                    value DOUBLE PRECISION NOT NULL,
+                    time TIMESTAMPTZ NOT NULL
+                )",
+                config.table
+            ))?;
+            client.insert(connected)
+        }
+    };
+
+    let mut transaction = client.transaction()?;
+    let statement = transaction.prepare(&format!(
+        "INSERT INTO {} (sensor, metric, value, time) VALUES ($1, $2, $3, $4)",
+        config.table
+    ))?;
+    for (sensor, metric, value, time) in rows {
+        transaction.execute(&statement, &[sensor, metric, value, time])?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+/// Topic and payload layout of popular firmware, read without configuring each field
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    /// Tasmota's `tele/<device>/SENSOR` telemetry, with each attached sensor's values in an object
+    /// named after it, e.g. `{"AM2301":{"Temperature":21.3,"Humidity":45.1},"TempUnit":"C"}`
+    Tasmota,
+    /// ESPHome's bare numbers on each entity's `<node>/sensor/<object_id>/state`, with `topic` as
+    /// the node's topic prefix and its `temperature_entity` and `humidity_entity` paired
+    Esphome,
+    /// A Gen1 Shelly H&T's bare numbers on `shellies/<id>/sensor/temperature` and `humidity`, with
+    /// `topic` as `shellies/<id>`
+    ShellyGen1,
+    /// A Gen2 or later Shelly H&T's `NotifyFullStatus` RPC notifications on `<id>/events/rpc`, with
+    /// the values in the `temperature:0` and `humidity:0` components
+    ShellyGen2,
+}
+
+impl Preset {
+    /// Temperature and humidity topics paired under `topic`, for firmware publishing them apart,
+    /// with the names of ESPHome's entities
+    pub fn split_topics(
+        self,
+        topic: &str,
+        temperature_entity: &str,
+        humidity_entity: &str,
+    ) -> Option<(String, String)> {
+        match self {
+            Self::Esphome => Some((
+                format!("{topic}/sensor/{temperature_entity}/state"),
+                format!("{topic}/sensor/{humidity_entity}/state"),
+            )),
+            Self::ShellyGen1 => Some((
+                format!("{topic}/sensor/temperature"),
+                format!("{topic}/sensor/humidity"),
+            )),
+            Self::Tasmota | Self::ShellyGen2 => None,
+        }
+    }
+
+    /// Rewrites a payload as the JSON object with `temperature`, `humidity`, and optionally
+    /// `pressure` and `unit` that everything reading payloads expects
+    pub fn normalize(self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        let json = || serde_json::from_slice::<Value>(payload).map_err(|e| e.to_string());
+        let normalized = match self {
+            Self::Tasmota => tasmota(&json()?)?,
+            Self::ShellyGen2 => match shelly_gen2(&json()?) {
+                Some(normalized) => normalized,
+                // other notifications are skipped like any payload without a reading
+                None => return Ok(payload.to_vec()),
+            },
+            // already bare numbers
+            Self::Esphome | Self::ShellyGen1 => return Ok(payload.to_vec()),
+        };
+        serde_json::to_vec(&normalized).map_err(|e| e.to_string())
+    }
+}
+
+/// Values of the first attached sensor reporting both temperature and humidity
+fn tasmota(value: &Value) -> Result<Value, String> {
+    let sensor = value
+        .as_object()
+        .into_iter()
+        .flat_map(Map::values)
+        .find(|sensor| sensor.get("Temperature").is_some() && sensor.get("Humidity").is_some())
+        .ok_or("No Tasmota sensor with Temperature and Humidity")?;
+    let mut normalized = json!({
+        "temperature": sensor["Temperature"],
+        "humidity": sensor["Humidity"],
+    });
+    // Tasmota reports pressure in mmHg or inHg if configured to, which would be misread as hPa
+    if value
+        .get("PressureUnit")
+        .is_none_or(|unit| unit.as_str() == Some("hPa"))
+    {
+        if let Some(pressure) = sensor.get("Pressure") {
+            normalized["pressure"] = pressure.clone();
+        }
+    }
+    if let Some(unit) = value.get("TempUnit") {
+        normalized["unit"] = unit.clone();
+    }
+    Ok(normalized)
+}
+
+/// Values of a notification with both temperature and humidity, which Gen2 devices send in full
+/// each time they wake
+fn shelly_gen2(value: &Value) -> Option<Value> {
+    let params = value.get("params")?;
+    let temperature = params.get("temperature:0")?.get("tC")?;
+    let humidity = params.get("humidity:0")?.get("rh")?;
+    Some(json!({
+        "temperature": temperature,
+        "humidity": humidity,
+        "unit": "C",
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(preset: Preset, payload: &str) -> Result<Value, String> {
+        let normalized = preset.normalize(payload.as_bytes())?;
+        serde_json::from_slice(&normalized).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn device_payloads() {
+        let cases = [
+            (
+                Preset::Tasmota,
+                r#"{"Time":"2024-01-31T08:15:00","AM2301":{"Temperature":21.3,"Humidity":45.1,"DewPoint":9.0},"TempUnit":"C"}"#,
+                json!({"temperature": 21.3, "humidity": 45.1, "unit": "C"}),
+            ),
+            (
+                Preset::Tasmota,
+                r#"{"Time":"2024-01-31T08:15:00","BME280":{"Temperature":70.3,"Humidity":45.1,"DewPoint":48.2,"Pressure":1013.2},"PressureUnit":"hPa","TempUnit":"F"}"#,
+                json!({"temperature": 70.3, "humidity": 45.1, "pressure": 1013.2, "unit": "F"}),
+            ),
+            (
+                Preset::Tasmota,
+                r#"{"Time":"2024-01-31T08:15:00","BME280":{"Temperature":21.3,"Humidity":45.1,"DewPoint":9.0,"Pressure":760.0},"PressureUnit":"mmHg","TempUnit":"C"}"#,
+                json!({"temperature": 21.3, "humidity": 45.1, "unit": "C"}),
+            ),
+            (
+                Preset::ShellyGen2,
+                r#"{"src":"shellyhtg3-84fce63e5a24","dst":"shellyhtg3-84fce63e5a24/events","method":"NotifyFullStatus","params":{"ts":1706688900.12,"devicepower:0":{"id":0,"battery":{"V":6.2,"percent":98},"external":{"present":false}},"humidity:0":{"id":0,"rh":45.1},"temperature:0":{"id":0,"tC":21.3,"tF":70.3}}}"#,
+                json!({"temperature": 21.3, "humidity": 45.1, "unit": "C"}),
+            ),
+            (
+                Preset::ShellyGen2,
+                r#"{"src":"shellyhtg3-84fce63e5a24","dst":"shellyhtg3-84fce63e5a24/events","method":"NotifyEvent","params":{"ts":1706688900.12,"events":[{"component":"sys","event":"sleep"}]}}"#,
+                json!({"src": "shellyhtg3-84fce63e5a24", "dst": "shellyhtg3-84fce63e5a24/events", "method": "NotifyEvent", "params": {"ts": 1706688900.12, "events": [{"component": "sys", "event": "sleep"}]}}),
+            ),
+            (Preset::Esphome, "21.30", json!(21.3)),
+            (Preset::ShellyGen1, "45.1", json!(45.1)),
+        ];
+        for (preset, payload, expected) in cases {
+            assert_eq!(normalize(preset, payload), Ok(expected), "{payload}");
+        }
+    }
+
+    #[test]
+    fn tasmota_without_humidity() {
+        assert!(normalize(
+            Preset::Tasmota,
+            r#"{"Time":"2024-01-31T08:15:00","DS18B20":{"Id":"01131B8A1E5B","Temperature":21.3},"TempUnit":"C"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn split_topics() {
+        assert_eq!(
+            Preset::Esphome.split_topics("living-room", "temperature", "humidity"),
+            Some((
+                String::from("living-room/sensor/temperature/state"),
+                String::from("living-room/sensor/humidity/state")
+            ))
+        );
+        assert_eq!(
+            Preset::ShellyGen1.split_topics("shellies/shellyht-6FE1B3", "temperature", "humidity"),
+            Some((
+                String::from("shellies/shellyht-6FE1B3/sensor/temperature"),
+                String::from("shellies/shellyht-6FE1B3/sensor/humidity")
+            ))
+        );
+        assert_eq!(
+            Preset::Tasmota.split_topics("tele/attic/SENSOR", "temperature", "humidity"),
+            None
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Derived values from temperature (°C), relative humidity (%), and pressure (hPa)
+
+use serde::Deserialize;
+
+/// Standard atmospheric pressure at sea level in hPa
+pub const STANDARD_PRESSURE: f64 = 1013.25;
+
+const A: f64 = 17.625;
+const B: f64 = 243.04;
+
+// Magnus coefficients for saturation over ice
+const A_ICE: f64 = 22.587;
+const B_ICE: f64 = 273.86;
+
+/// Coefficients of the Magnus formula used for dewpoint and saturation vapor pressure, defaulting
+/// to Alduchov and Eskridge's
+#[derive(Clone, Copy, Deserialize)]
+pub struct Magnus {
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Default for Magnus {
+    fn default() -> Self {
+        Self { a: A, b: B }
+    }
+}
+
+// Arden Buck (1996) coefficients over water
+const BUCK_B: f64 = 18.678;
+const BUCK_C: f64 = 257.14;
+const BUCK_D: f64 = 234.5;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DewpointFormula {
+    /// Magnus formula, accurate to within 0.35 °C between -45 °C and 60 °C
+    #[default]
+    Magnus,
+    /// Magnus form with Arden Buck's (1996) coefficients and `b - t/d` term, slightly more accurate
+    /// at temperature extremes. Buck's pressure dependent enhancement factor isn't applied, and
+    /// since the inversion leaves out the `t/d` term at the dewpoint, saturated air's dewpoint comes
+    /// out a little below its temperature.
+    ArdenBuck,
+    /// Lawrence's linear approximation, only reasonable above 50% relative humidity
+    Lawrence,
+}
+
+impl DewpointFormula {
+    /// Name as written in configs
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Magnus => "magnus",
+            Self::ArdenBuck => "arden_buck",
+            Self::Lawrence => "lawrence",
+        }
+    }
+
+    /// Dewpoint in °C
+    pub fn dewpoint(self, magnus: Magnus, temperature: f64, humidity: f64) -> f64 {
+        let t = temperature;
+        match self {
+            Self::Magnus => {
+                let Magnus { a, b } = magnus;
+                let c = (a * t) / (b + t);
+                let ln_rh = (humidity / 100.0).ln();
+
+                (b * (ln_rh + c)) / (a - ln_rh - c)
+            }
+            Self::ArdenBuck => {
+                let gamma =
+                    (humidity / 100.0 * ((BUCK_B - t / BUCK_D) * (t / (BUCK_C + t))).exp()).ln();
+
+                (BUCK_C * gamma) / (BUCK_B - gamma)
+            }
+            Self::Lawrence => t - (100.0 - humidity) / 5.0,
+        }
+    }
+}
+
+/// Frost point in °C, the temperature at which the air's water vapor would saturate over ice
+///
+/// Relative humidity is taken to be relative to water, as reported by practically every
+/// hygrometer, even below freezing.
+pub fn frost_point(magnus: Magnus, temperature: f64, humidity: f64) -> f64 {
+    let ln_e = (vapor_pressure(magnus, temperature, humidity) / 6.1121).ln();
+
+    (B_ICE * ln_e) / (A_ICE - ln_e)
+}
+
+/// Saturation vapor pressure over water in hPa using the Magnus formula
+pub fn saturation_vapor_pressure(magnus: Magnus, temperature: f64) -> f64 {
+    let Magnus { a, b } = magnus;
+    6.1094 * ((a * temperature) / (b + temperature)).exp()
+}
+
+/// Actual vapor pressure in hPa
+pub fn vapor_pressure(magnus: Magnus, temperature: f64, humidity: f64) -> f64 {
+    saturation_vapor_pressure(magnus, temperature) * humidity / 100.0
+}
+
+/// Absolute humidity in g/m³
+pub fn absolute_humidity(magnus: Magnus, temperature: f64, humidity: f64) -> f64 {
+    // ideal gas law with the specific gas constant of water vapor, 461.5 J/(kg·K)
+    vapor_pressure(magnus, temperature, humidity) * 100.0 / (461.5 * (temperature + 273.15))
+        * 1000.0
+}
+
+/// Relative humidity in % of air cooled to `surface_temperature` (°C) against a surface, which
+/// reaches 100% once the surface is at or below the dewpoint
+pub fn surface_humidity(
+    magnus: Magnus,
+    temperature: f64,
+    humidity: f64,
+    surface_temperature: f64,
+) -> f64 {
+    let saturation = saturation_vapor_pressure(magnus, surface_temperature);
+    (vapor_pressure(magnus, temperature, humidity) / saturation * 100.0).min(100.0)
+}
+
+/// Vapor pressure deficit in kPa
+pub fn vapor_pressure_deficit(magnus: Magnus, temperature: f64, humidity: f64) -> f64 {
+    let saturation = saturation_vapor_pressure(magnus, temperature);
+    (saturation - vapor_pressure(magnus, temperature, humidity)) / 10.0
+}
+
+/// Mixing ratio in kg of water vapor per kg of dry air
+pub fn mixing_ratio(magnus: Magnus, temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let e = vapor_pressure(magnus, temperature, humidity);
+    0.622 * e / (pressure - e)
+}
+
+/// Specific humidity in kg of water vapor per kg of moist air
+pub fn specific_humidity(magnus: Magnus, temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let w = mixing_ratio(magnus, temperature, humidity, pressure);
+    w / (1.0 + w)
+}
+
+/// Thermodynamic wet-bulb temperature in °C, solved from the psychrometer equation
+pub fn wet_bulb(magnus: Magnus, temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let e = vapor_pressure(magnus, temperature, humidity);
+    // vapor pressure implied by a wet-bulb temperature, which only increases with it
+    let implied = |tw: f64| {
+        saturation_vapor_pressure(magnus, tw)
+            - 6.6e-4 * 0.001_15_f64.mul_add(tw, 1.0) * pressure * (temperature - tw)
+    };
+
+    let (mut low, mut high) = (temperature - 100.0, temperature);
+    for _ in 0..50 {
+        let mid = (low + high) / 2.0;
+        if implied(mid) > e {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+
+    (low + high) / 2.0
+}
+
+/// Specific enthalpy of moist air in kJ per kg of dry air
+pub fn enthalpy(magnus: Magnus, temperature: f64, humidity: f64, pressure: f64) -> f64 {
+    let w = mixing_ratio(magnus, temperature, humidity, pressure);
+    1.006_f64.mul_add(temperature, w * 1.86_f64.mul_add(temperature, 2501.0))
+}
+
+/// NOAA heat index in °C, using the Rothfusz regression and its adjustments where the simple
+/// formula isn't accurate enough
+pub fn heat_index(temperature: f64, humidity: f64) -> f64 {
+    let t = temperature.mul_add(1.8, 32_f64);
+    let rh = humidity;
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return (simple - 32.0) / 1.8;
+    }
+
+    let mut hi = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh
+        - 0.224_755_41 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+    }
+
+    (hi - 32.0) / 1.8
+}
+
+/// Environment Canada humidex, a dimensionless value on roughly the same scale as °C
+pub fn humidex(temperature: f64, dewpoint: f64) -> f64 {
+    let dewpoint_k = dewpoint + 273.15;
+    let e = 6.11 * (5417.7530 * (1.0 / 273.16 - 1.0 / dewpoint_k)).exp();
+
+    0.5555_f64.mul_add(e - 10.0, temperature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[track_caller]
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{actual} isn't within {tolerance} of {expected}"
+        );
+    }
+
+    fn fahrenheit(celsius: f64) -> f64 {
+        celsius.mul_add(1.8, 32.0)
+    }
+
+    fn celsius(fahrenheit: f64) -> f64 {
+        (fahrenheit - 32.0) / 1.8
+    }
+
+    #[test]
+    fn dewpoint_formulas() {
+        let magnus = Magnus::default();
+        assert_close(
+            DewpointFormula::Magnus.dewpoint(magnus, 20.0, 50.0),
+            9.26,
+            0.01,
+        );
+        assert_close(
+            DewpointFormula::ArdenBuck.dewpoint(magnus, 20.0, 50.0),
+            9.25,
+            0.01,
+        );
+        assert_close(
+            DewpointFormula::Lawrence.dewpoint(magnus, 20.0, 50.0),
+            10.0,
+            1e-9,
+        );
+        assert_close(
+            DewpointFormula::Magnus.dewpoint(magnus, -10.0, 80.0),
+            -12.8,
+            0.01,
+        );
+        assert_close(
+            DewpointFormula::ArdenBuck.dewpoint(magnus, -10.0, 80.0),
+            -12.83,
+            0.01,
+        );
+    }
+
+    #[test]
+    fn dewpoint_at_saturation() {
+        let magnus = Magnus::default();
+        for t in [-20.0, 0.0, 20.0, 40.0] {
+            assert_close(DewpointFormula::Magnus.dewpoint(magnus, t, 100.0), t, 1e-9);
+            assert_close(
+                DewpointFormula::Lawrence.dewpoint(magnus, t, 100.0),
+                t,
+                1e-9,
+            );
+        }
+        // the inversion leaves out Buck's t/d term at the dewpoint, increasingly off when hot
+        assert_close(
+            DewpointFormula::ArdenBuck.dewpoint(magnus, 20.0, 100.0),
+            20.0,
+            0.1,
+        );
+        assert_close(
+            DewpointFormula::ArdenBuck.dewpoint(magnus, 40.0, 100.0),
+            40.0,
+            0.5,
+        );
+    }
+
+    #[test]
+    fn dewpoint_is_undefined_without_humidity() {
+        // readings below filter::MIN_HUMIDITY are rejected before getting here
+        let magnus = Magnus::default();
+        assert!(DewpointFormula::Magnus.dewpoint(magnus, 20.0, 0.0).is_nan());
+        assert!(DewpointFormula::Magnus
+            .dewpoint(magnus, 20.0, 0.1)
+            .is_finite());
+    }
+
+    #[test]
+    fn magnus_coefficients_are_consistent() {
+        // the vapor pressure of air is the saturation vapor pressure at its dewpoint, whichever
+        // coefficients are configured
+        for magnus in [Magnus::default(), Magnus { a: 17.27, b: 237.7 }] {
+            for (t, rh) in [(20.0, 50.0), (-5.0, 90.0), (35.0, 20.0)] {
+                let dewpoint = DewpointFormula::Magnus.dewpoint(magnus, t, rh);
+                assert_close(
+                    vapor_pressure(magnus, t, rh),
+                    saturation_vapor_pressure(magnus, dewpoint),
+                    1e-9,
+                );
+                assert_close(surface_humidity(magnus, t, rh, dewpoint), 100.0, 1e-9);
+            }
+        }
+        let other = Magnus { a: 17.27, b: 237.7 };
+        assert!(
+            (absolute_humidity(other, 30.0, 50.0)
+                - absolute_humidity(Magnus::default(), 30.0, 50.0))
+            .abs()
+                > 0.01
+        );
+    }
+
+    #[test]
+    fn frost_point_below_freezing() {
+        let magnus = Magnus::default();
+        // vapor saturates over ice at a higher temperature than over water
+        let frost = frost_point(magnus, -10.0, 80.0);
+        assert_close(frost, -11.4, 0.05);
+        assert!(frost > DewpointFormula::Magnus.dewpoint(magnus, -10.0, 80.0));
+        // saturated over water below freezing is supersaturated over ice
+        assert!(frost_point(magnus, -10.0, 100.0) > -10.0);
+        // the two meet at the triple point
+        assert_close(frost_point(magnus, 0.0, 100.0), 0.0, 0.01);
+    }
+
+    #[test]
+    fn heat_index_matches_noaa_table() {
+        // (°F, % RH, heat index in °F) from the NWS heat index chart
+        for (t, rh, expected) in [
+            (80.0, 40.0, 80.0),
+            (80.0, 90.0, 86.0),
+            (90.0, 40.0, 91.0),
+            (90.0, 50.0, 95.0),
+            (90.0, 70.0, 106.0),
+            (90.0, 100.0, 132.0),
+            (100.0, 40.0, 109.0),
+            (100.0, 50.0, 118.0),
+        ] {
+            let heat_index = fahrenheit(heat_index(celsius(t), rh)).round();
+            assert_close(heat_index, expected, 1e-9);
+        }
+    }
+
+    #[test]
+    fn heat_index_adjustments() {
+        // the simple formula below 80 °F
+        assert_close(fahrenheit(heat_index(celsius(70.0), 50.0)), 69.05, 0.01);
+        // dry air lowers the regression's result
+        assert_close(fahrenheit(heat_index(celsius(95.0), 10.0)), 89.45, 0.01);
+        // humid air raises it
+        assert_close(fahrenheit(heat_index(celsius(84.0), 90.0)), 98.34, 0.01);
+    }
+
+    #[test]
+    fn humidex_matches_environment_canada() {
+        assert_eq!(humidex(30.0, 15.0).round(), 34.0);
+    }
+
+    #[test]
+    fn ashrae_chart_points() {
+        let magnus = Magnus::default();
+        // 25 °C and 50% RH at sea level: 9.9 g/kg, 50.3 kJ/kg, 17.9 °C wet-bulb, 11.5 g/m³
+        let p = STANDARD_PRESSURE;
+        assert_close(mixing_ratio(magnus, 25.0, 50.0, p) * 1000.0, 9.9, 0.1);
+        assert_close(enthalpy(magnus, 25.0, 50.0, p), 50.3, 0.2);
+        assert_close(wet_bulb(magnus, 25.0, 50.0, p), 17.9, 0.15);
+        assert_close(absolute_humidity(magnus, 25.0, 50.0), 11.5, 0.1);
+        // 20 °C and 50% RH: 7.3 g/kg, 38.5 kJ/kg, 13.8 °C wet-bulb
+        assert_close(mixing_ratio(magnus, 20.0, 50.0, p) * 1000.0, 7.3, 0.1);
+        assert_close(enthalpy(magnus, 20.0, 50.0, p), 38.5, 0.2);
+        assert_close(wet_bulb(magnus, 20.0, 50.0, p), 13.8, 0.15);
+    }
+
+    #[test]
+    fn lower_pressure() {
+        let magnus = Magnus::default();
+        let e = vapor_pressure(magnus, 25.0, 50.0);
+        let w = mixing_ratio(magnus, 25.0, 50.0, 850.0);
+        assert_close(w, 0.622 * e / (850.0 - e), 1e-12);
+        assert!(w > mixing_ratio(magnus, 25.0, 50.0, STANDARD_PRESSURE));
+        assert_close(
+            specific_humidity(magnus, 25.0, 50.0, 850.0),
+            w / (1.0 + w),
+            1e-12,
+        );
+        assert!(
+            enthalpy(magnus, 25.0, 50.0, 850.0) > enthalpy(magnus, 25.0, 50.0, STANDARD_PRESSURE)
+        );
+        assert!(
+            wet_bulb(magnus, 25.0, 50.0, 850.0) < wet_bulb(magnus, 25.0, 50.0, STANDARD_PRESSURE)
+        );
+    }
+
+    #[test]
+    fn dry_and_saturated_air() {
+        let magnus = Magnus::default();
+        let p = STANDARD_PRESSURE;
+        assert_close(vapor_pressure(magnus, 20.0, 0.0), 0.0, 1e-12);
+        assert_close(mixing_ratio(magnus, 20.0, 0.0, p), 0.0, 1e-12);
+        assert_close(enthalpy(magnus, 20.0, 0.0, p), 1.006 * 20.0, 1e-9);
+        assert_close(
+            vapor_pressure_deficit(magnus, 20.0, 0.0),
+            saturation_vapor_pressure(magnus, 20.0) / 10.0,
+            1e-12,
+        );
+        assert_close(vapor_pressure_deficit(magnus, 20.0, 100.0), 0.0, 1e-12);
+        assert_close(saturation_vapor_pressure(magnus, 0.0), 6.1094, 1e-9);
+    }
+
+    #[test]
+    fn wet_bulb_converges() {
+        let magnus = Magnus::default();
+        let p = STANDARD_PRESSURE;
+        for (t, rh) in [
+            (20.0, 100.0),
+            (20.0, 0.0),
+            (40.0, 10.0),
+            (-10.0, 50.0),
+            (45.0, 95.0),
+        ] {
+            let tw = wet_bulb(magnus, t, rh, p);
+            assert!(tw <= t && tw > t - 100.0, "{tw} for {t} °C / {rh}%");
+            // solves the psychrometer equation to well within the published precision
+            let implied = saturation_vapor_pressure(magnus, tw)
+                - 6.6e-4 * 0.001_15_f64.mul_add(tw, 1.0) * p * (t - tw);
+            assert_close(implied, vapor_pressure(magnus, t, rh), 1e-6);
+        }
+        assert_close(wet_bulb(magnus, 20.0, 100.0, p), 20.0, 1e-9);
+        // the dry air limit from the chart
+        assert_close(wet_bulb(magnus, 20.0, 0.0, p), 6.0, 0.15);
+    }
+}
@@ -0,0 +1,40 @@
+//! One-off publishes and subscriptions with the configured credentials, for debugging a broker
+//! setup without other MQTT tools
+
+use crate::client_manager::ClientManager;
+use crate::config::Config;
+use crate::event::Event;
+use std::error::Error;
+use std::sync::mpsc::Receiver;
+
+/// Publishes a message to the broker sensors are read from
+pub fn publish(
+    config: &Config,
+    topic: &str,
+    payload: &str,
+    retain: bool,
+    trace: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut clients = ClientManager::connect_source(config, trace)?;
+    clients.publish(None, topic, payload, retain);
+    clients.disconnect();
+    Ok(())
+}
+
+/// Prints each payload received on `topic` on its own line until `rx` receives `Event::Shutdown`
+pub fn subscribe(
+    config: &Config,
+    topic: &str,
+    trace: bool,
+    rx: &Receiver<Event>,
+) -> Result<(), Box<dyn Error>> {
+    let mut clients = ClientManager::connect_source(config, trace)?;
+    let handler = Box::new(|payload: Vec<u8>| {
+        println!("{}", String::from_utf8_lossy(&payload));
+        None
+    });
+    clients.subscribe_source(topic, handler)?;
+    let _ = rx.iter().find(|event| matches!(event, Event::Shutdown));
+    clients.disconnect();
+    Ok(())
+}
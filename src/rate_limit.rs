@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Token bucket allowing up to `burst` events at once, refilling at `burst` per `interval`
+pub struct TokenBucket {
+    burst: f64,
+    /// Tokens added per second
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(burst: u32, interval: Duration) -> Self {
+        Self {
+            burst: f64::from(burst),
+            rate: f64::from(burst) / interval.as_secs_f64(),
+            tokens: f64::from(burst),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Whether an event is allowed now, using up a token if it is
+    pub fn take(&mut self) -> bool {
+        self.take_at(Instant::now())
+    }
+
+    fn take_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = self.rate.mul_add(elapsed, self.tokens).min(self.burst);
+        self.updated = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Limits on outbound publishes, in total and to each topic, both per minute
+pub struct PublishLimit {
+    total: Option<TokenBucket>,
+    per_topic: Option<u32>,
+    topics: HashMap<String, TokenBucket>,
+}
+
+impl PublishLimit {
+    pub fn new(total: Option<u32>, per_topic: Option<u32>) -> Self {
+        Self {
+            total: total.map(|burst| TokenBucket::new(burst, Duration::from_secs(60))),
+            per_topic,
+            topics: HashMap::new(),
+        }
+    }
+
+    /// Whether a message may be published to `topic` now
+    pub fn take(&mut self, topic: &str) -> bool {
+        if let Some(burst) = self.per_topic {
+            let bucket = self
+                .topics
+                .entry(topic.to_string())
+                .or_insert_with(|| TokenBucket::new(burst, Duration::from_secs(60)));
+            if !bucket.take() {
+                return false;
+            }
+        }
+        self.total.as_mut().is_none_or(TokenBucket::take)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_empty() {
+        let mut bucket = TokenBucket::new(3, Duration::from_secs(60));
+        let now = bucket.updated;
+        assert!(bucket.take_at(now));
+        assert!(bucket.take_at(now));
+        assert!(bucket.take_at(now));
+        assert!(!bucket.take_at(now));
+    }
+
+    #[test]
+    fn refills_at_rate() {
+        let mut bucket = TokenBucket::new(3, Duration::from_secs(60));
+        let start = bucket.updated;
+        for _ in 0..3 {
+            assert!(bucket.take_at(start));
+        }
+        // one token every 20 seconds
+        assert!(!bucket.take_at(start + Duration::from_secs(19)));
+        assert!(bucket.take_at(start + Duration::from_secs(20)));
+        assert!(!bucket.take_at(start + Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn refill_is_capped_at_burst() {
+        let mut bucket = TokenBucket::new(2, Duration::from_secs(60));
+        let later = bucket.updated + Duration::from_secs(3600);
+        assert!(bucket.take_at(later));
+        assert!(bucket.take_at(later));
+        assert!(!bucket.take_at(later));
+    }
+
+    #[test]
+    fn per_topic_and_total() {
+        let mut limit = PublishLimit::new(Some(3), Some(2));
+        assert!(limit.take("a"));
+        assert!(limit.take("a"));
+        assert!(!limit.take("a"));
+        assert!(limit.take("b"));
+        // the total is used up by a, b, and the second a
+        assert!(!limit.take("c"));
+    }
+
+    #[test]
+    fn unlimited() {
+        let mut limit = PublishLimit::new(None, None);
+        assert!((0..1000).all(|_| limit.take("a")));
+    }
+}
@@ -0,0 +1,71 @@
+//! Messages recorded from a broker fed back through sensors' handlers, for trying formula or
+//! config changes against real history
+//!
+//! Each line of a capture is `[UNIX_TIME] TOPIC PAYLOAD`, as written by
+//! `mosquitto_sub -v -F '%U %t %p'`, or by `mosquitto_sub -v` without times.
+
+use crate::event::{Event, Sender};
+use crate::sensor::Handler;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Replays `path` to the handlers in `sources` from a background thread, `speed` times as fast as
+/// it was recorded, and shuts down once it's done
+pub fn start(
+    path: &Path,
+    speed: f64,
+    sources: Vec<(String, Handler)>,
+    tx: Sender,
+) -> Result<(), Box<dyn Error>> {
+    let capture = File::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let path = path.to_path_buf();
+    let handlers: HashMap<String, Handler> = sources.into_iter().collect();
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut first = None;
+        let mut replayed = 0;
+        for (number, line) in BufReader::new(capture).lines().enumerate() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Error reading {}: {e}", path.display());
+                    break;
+                }
+            };
+            let Some((time, topic, payload)) = parse(&line) else {
+                warn!("Skipping line {} of {}", number + 1, path.display());
+                continue;
+            };
+            if let Some(time) = time {
+                let first = *first.get_or_insert(time);
+                let due = Duration::try_from_secs_f64((time - first) / speed).unwrap_or_default();
+                thread::sleep(due.saturating_sub(started.elapsed()));
+            }
+            if let Some(handler) = handlers.get(topic) {
+                handler(payload.as_bytes().to_vec());
+                replayed += 1;
+            }
+        }
+        info!("Replayed {replayed} messages from {}", path.display());
+        let _ = tx.send(Event::Shutdown);
+    });
+    Ok(())
+}
+
+/// Time (if recorded), topic, and payload of a line
+fn parse(line: &str) -> Option<(Option<f64>, &str, &str)> {
+    let (first, rest) = line.split_once(' ')?;
+    match first.parse() {
+        Ok(time) => {
+            let (topic, payload) = rest.split_once(' ')?;
+            Some((Some(time), topic, payload))
+        }
+        Err(_) => Some((None, first, rest)),
+    }
+}
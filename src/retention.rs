@@ -0,0 +1,61 @@
+//! Deletes history older than the `retention` period from local sinks, so they don't grow until
+//! the disk is full
+
+use crate::config::Config;
+use crate::csv_log;
+#[cfg(feature = "sqlite")]
+use crate::{sqlite, timestamp};
+use log::{info, warn};
+#[cfg(not(feature = "sqlite"))]
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time between prunes, which is plenty with retention measured in days
+const INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Prunes the `sqlite` database and `csv` directory now and every day after from a background
+/// thread, if the config has a `retention` and either of them
+pub fn start(config: &Config) {
+    let Some(days) = config.retention else {
+        return;
+    };
+    #[cfg(feature = "sqlite")]
+    let sqlite = config.sqlite.clone();
+    #[cfg(not(feature = "sqlite"))]
+    let sqlite: Option<PathBuf> = None;
+    let csv = config.csv.as_ref().map(|csv| csv.directory.clone());
+    if sqlite.is_none() && csv.is_none() {
+        return;
+    }
+    let Some(retention) = period(days) else {
+        return;
+    };
+    thread::spawn(move || loop {
+        // checked when loading the config, but the clock could have been set back since
+        let before = SystemTime::now()
+            .checked_sub(retention)
+            .unwrap_or(UNIX_EPOCH);
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &sqlite {
+            match sqlite::prune(path, &timestamp::format(before)) {
+                Ok(0) => {}
+                Ok(deleted) => info!("Pruned {deleted} values from {}", path.display()),
+                Err(e) => warn!("Error pruning {}: {e}", path.display()),
+            }
+        }
+        if let Some(directory) = &csv {
+            match csv_log::prune(directory, before) {
+                Ok(0) => {}
+                Ok(deleted) => info!("Pruned {deleted} files from {}", directory.display()),
+                Err(e) => warn!("Error pruning {}: {e}", directory.display()),
+            }
+        }
+        thread::sleep(INTERVAL);
+    });
+}
+
+/// A `retention` in days as a duration, unless it's too long to represent
+pub fn period(days: u64) -> Option<Duration> {
+    days.checked_mul(24 * 60 * 60).map(Duration::from_secs)
+}
@@ -0,0 +1,152 @@
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+use std::error::Error;
+use std::path::Path;
+
+/// Operations a call to `process` may take before it's stopped, so a script stuck in a loop
+/// doesn't hang the handler thread of every sensor on its broker
+const MAX_OPERATIONS: u64 = 1_000_000;
+/// Depth of nested function calls, so runaway recursion fails instead of overflowing the stack
+const MAX_CALL_LEVELS: usize = 32;
+
+/// A sensor's Rhai script, whose `process(payload)` function is called with each decoded source
+/// payload and returns an array of `#{topic, payload}` maps to publish
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let engine = engine();
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Runs the script on a JSON payload, returning the topics and payloads it outputs
+    pub fn process(&self, payload: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let payload = self.engine.parse_json(payload, true)?;
+        let outputs: Dynamic =
+            self.engine
+                .call_fn(&mut Scope::new(), &self.ast, "process", (payload,))?;
+        if outputs.is_unit() {
+            return Ok(Vec::new());
+        }
+
+        let outputs = outputs
+            .try_cast::<Array>()
+            .ok_or("process() must return an array")?;
+        outputs
+            .into_iter()
+            .map(|output| {
+                let mut output = output
+                    .try_cast::<Map>()
+                    .ok_or("process() must return an array of maps")?;
+                let topic = output
+                    .remove("topic")
+                    .and_then(|topic| topic.into_string().ok())
+                    .ok_or("Script output is missing a `topic` string")?;
+                let payload = output
+                    .remove("payload")
+                    .ok_or("Script output is missing a `payload`")?;
+                let payload = if payload.is::<Map>() {
+                    payload
+                        .try_cast::<Map>()
+                        .map(|map| rhai::format_map_as_json(&map))
+                        .unwrap_or_default()
+                } else {
+                    payload.to_string()
+                };
+                Ok((topic, payload))
+            })
+            .collect()
+    }
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine.set_max_call_levels(MAX_CALL_LEVELS);
+    engine
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(source: &str) -> Script {
+        let engine = engine();
+        let ast = engine.compile(source).unwrap();
+        Script { engine, ast }
+    }
+
+    fn error(source: &str) -> String {
+        script(source)
+            .process(r#"{"temperature":21.5}"#)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn outputs() {
+        let converter = script(
+            r#"
+            fn process(payload) {
+                [
+                    #{ topic: "attic/fahrenheit", payload: payload.temperature * 1.8 + 32.0 },
+                    #{ topic: "attic/json", payload: #{ celsius: payload.temperature } },
+                ]
+            }
+            "#,
+        );
+        assert_eq!(
+            converter.process(r#"{"temperature":21.5}"#).unwrap(),
+            [
+                (String::from("attic/fahrenheit"), String::from("70.7")),
+                (
+                    String::from("attic/json"),
+                    String::from(r#"{"celsius":21.5}"#)
+                ),
+            ]
+        );
+        assert!(script("fn process(payload) {}")
+            .process("{}")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn output_shape_errors() {
+        assert_eq!(
+            error("fn process(payload) { 42 }"),
+            "process() must return an array"
+        );
+        assert_eq!(
+            error("fn process(payload) { [42] }"),
+            "process() must return an array of maps"
+        );
+        assert_eq!(
+            error(r#"fn process(payload) { [#{ payload: "42" }] }"#),
+            "Script output is missing a `topic` string"
+        );
+        assert_eq!(
+            error(r#"fn process(payload) { [#{ topic: 42, payload: "42" }] }"#),
+            "Script output is missing a `topic` string"
+        );
+        assert_eq!(
+            error(r#"fn process(payload) { [#{ topic: "attic" }] }"#),
+            "Script output is missing a `payload`"
+        );
+    }
+
+    #[test]
+    fn limits() {
+        assert!(error("fn process(payload) { loop {} }").contains("Too many operations"));
+        // Rhai's error for exceeding the call levels, before the real stack would overflow
+        assert!(
+            error("fn recurse(n) { recurse(n + 1) } fn process(payload) { recurse(0) }")
+                .contains("Stack overflow")
+        );
+    }
+}
@@ -0,0 +1,1902 @@
+use crate::checkpoint::{self, SensorState};
+use crate::compression::Compression;
+use crate::config::{self, OutputFormat, Source};
+use crate::discovery;
+use crate::encoding::PayloadEncoding;
+use crate::event::{Event, Latest, Message, Sender};
+#[cfg(feature = "expressions")]
+use crate::expression::{self, Expression, Variables};
+use crate::filter::{self, OutOfRange, Smoothed, SpikeFilter};
+use crate::metric::{Metric, Reading};
+#[cfg(feature = "otlp")]
+use crate::otlp::{self, Span};
+use crate::preset::Preset;
+#[cfg(feature = "script")]
+use crate::script::Script;
+use crate::statistics::{self, Samples, Statistic};
+use crate::timestamp;
+use crate::unit::Unit;
+#[cfg(feature = "expressions")]
+use evalexpr::Node;
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+pub type Handler = Box<dyn Fn(Vec<u8>) -> Option<Vec<u8>> + Send>;
+
+#[derive(Deserialize)]
+struct SensorRecord {
+    temperature: f64,
+    humidity: f64,
+    pressure: Option<f64>,
+    /// Temperature unit, overriding the sensor's `input_unit`
+    unit: Option<Unit>,
+}
+
+/// Label, device class, and unit of zigbee2mqtt fields commonly passed through
+const KNOWN_FIELDS: [(&str, &str, Option<&str>, Option<&str>); 3] = [
+    ("battery", "Battery", Some("battery"), Some("%")),
+    ("linkquality", "Link quality", None, Some("lqi")),
+    ("voltage", "Voltage", Some("voltage"), Some("mV")),
+];
+
+/// (metric, component) of the range warning binary sensor
+const RANGE_WARNING: (&str, &str) = ("range_warning", "binary_sensor");
+
+#[derive(Clone, Copy)]
+enum Field {
+    Temperature,
+    Humidity,
+}
+
+impl Field {
+    const fn key(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+        }
+    }
+
+    /// `{metric}` of the field's `number` entity when the sensor has `tunable_offsets`
+    const fn offset_key(self) -> &'static str {
+        match self {
+            Self::Temperature => "temperature_offset",
+            Self::Humidity => "humidity_offset",
+        }
+    }
+
+    /// Largest magnitude of the field's offset
+    const fn max_offset(self) -> f64 {
+        match self {
+            Self::Temperature => config::MAX_TEMPERATURE_OFFSET,
+            Self::Humidity => config::MAX_HUMIDITY_OFFSET,
+        }
+    }
+}
+
+/// Field, label, and unit of each offset exposed as a `number` entity
+const OFFSETS: [(Field, &str, &str); 2] = [
+    (Field::Temperature, "Temperature offset", "°C"),
+    (Field::Humidity, "Humidity offset", "%"),
+];
+
+/// Latest value (and when it arrived) from each half of a split sensor
+#[derive(Default)]
+struct Pending {
+    /// In °C
+    temperature: Option<(f64, Instant)>,
+    humidity: Option<(f64, Instant)>,
+}
+
+/// State shared by all of a sensor's handlers
+struct Context {
+    sensor: config::Sensor,
+    tx: Sender,
+    /// Temperature and humidity calibration offsets, which Home Assistant can change if the
+    /// sensor has `tunable_offsets`
+    offsets: Mutex<(f64, f64)>,
+    pending: Mutex<Pending>,
+    /// Latest value from the sensor's `pressure_topic`
+    pressure: Mutex<Option<f64>>,
+    /// Latest value from the sensor's `surface_temperature_topic`
+    surface_temperature: Mutex<Option<f64>>,
+    spike_filter: Mutex<SpikeFilter>,
+    /// Smoothing state for temperature and humidity
+    smoothed: Mutex<(Smoothed, Smoothed)>,
+    /// Last value and payload published for each metric, and when
+    published: Mutex<HashMap<Metric, Published>>,
+    /// Temperature and dewpoint (°C) over the sensor's `trend_window`
+    trends: Mutex<(Samples, Samples)>,
+    /// Values of each numeric metric over the sensor's longest statistics window
+    samples: Mutex<HashMap<Metric, Samples>>,
+    /// Last state published for the sensor's `range_warning`
+    range_warning: Mutex<Option<bool>>,
+    /// Latest payload of each of the sensor's `passthrough` fields
+    passthrough: Mutex<BTreeMap<String, String>>,
+    #[cfg(feature = "script")]
+    script: Option<Script>,
+    /// The sensor's expressions, compiled
+    #[cfg(feature = "expressions")]
+    expressions: Vec<(Expression, Node)>,
+    /// Latest numeric fields of the source payload, for expressions
+    #[cfg(feature = "expressions")]
+    fields: Mutex<BTreeMap<String, f64>>,
+    /// Last payload published when `output_format` is `json`
+    json: Mutex<Option<String>>,
+    /// When each source topic last received a message
+    last_seen: Mutex<HashMap<String, Instant>>,
+    /// Availability last published to the sensor's `availability_topic`
+    online: Mutex<Option<bool>>,
+    /// Whether zigbee2mqtt last reported each source device as online
+    source_online: Mutex<HashMap<String, bool>>,
+    /// Whether to send spans of each reading's processing, for export
+    spans: bool,
+}
+
+struct Published {
+    value: f64,
+    payload: String,
+    /// Payload as published to the metric's own topic, possibly with a timestamp
+    state: String,
+    at: Instant,
+}
+
+/// A metric, statistic, or passed through field, published with `key` as `{metric}` or as `key`
+/// in a JSON payload
+struct Value {
+    key: String,
+    component: &'static str,
+    payload: String,
+}
+
+/// Numeric payloads as JSON numbers, anything else as a string
+fn json_value(payload: &str) -> serde_json::Value {
+    payload.parse::<f64>().map_or_else(
+        |_| serde_json::json!(payload),
+        |number| serde_json::json!(number),
+    )
+}
+
+/// When and from which topic a reading was calculated
+struct Updated {
+    last_updated: String,
+    source: String,
+}
+
+impl Updated {
+    fn insert(&self, object: &mut serde_json::Map<String, serde_json::Value>) {
+        object.insert(
+            String::from("last_updated"),
+            serde_json::json!(self.last_updated),
+        );
+        object.insert(String::from("source"), serde_json::json!(self.source));
+    }
+}
+
+/// JSON object of values, with numeric payloads as numbers
+fn json_payload(values: Vec<Value>, updated: Option<&Updated>) -> String {
+    let mut object: serde_json::Map<String, serde_json::Value> = values
+        .into_iter()
+        .map(|Value { key, payload, .. }| (key, json_value(&payload)))
+        .collect();
+    if let Some(updated) = updated {
+        updated.insert(&mut object);
+    }
+    serde_json::Value::Object(object).to_string()
+}
+
+/// State payload for a value on its own topic, wrapped in JSON as `value` if timestamped
+fn state_payload(payload: String, updated: Option<&Updated>) -> String {
+    let Some(updated) = updated else {
+        return payload;
+    };
+    let mut object = serde_json::Map::new();
+    object.insert(String::from("value"), json_value(&payload));
+    updated.insert(&mut object);
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Topics to subscribe to for a sensor, along with their handlers
+pub struct Subscriptions {
+    /// On the broker sensors are read from
+    pub sources: Vec<(String, Handler)>,
+    /// On the output broker, which Home Assistant sends commands through
+    pub commands: Vec<(String, Handler)>,
+    pub handle: Handle,
+}
+
+/// Access to a sensor's state from outside its handlers
+pub struct Handle {
+    context: Arc<Context>,
+    /// Thread republishing metrics and checking for stale sources, which stops once the sender is
+    /// dropped
+    timers: Option<(mpsc::Sender<()>, JoinHandle<()>)>,
+}
+
+impl Handle {
+    /// The sensor's last published values and statistics samples, to restore after a restart
+    pub fn checkpoint(&self) -> SensorState {
+        let context = &self.context;
+        let samples = |samples: &Samples| {
+            samples
+                .values()
+                .map(|(at, value)| (checkpoint::unix(at), value))
+                .collect()
+        };
+        let trends = context.trends.lock().unwrap();
+        let trends = (samples(&trends.0), samples(&trends.1));
+        let published = context
+            .published
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(metric, last)| {
+                let last = checkpoint::Published {
+                    value: last.value,
+                    payload: last.payload.clone(),
+                    state: last.state.clone(),
+                    at: checkpoint::unix(last.at),
+                };
+                (metric.name().to_string(), last)
+            })
+            .collect();
+        let statistics = context
+            .samples
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(metric, values)| (metric.name().to_string(), samples(values)))
+            .collect();
+        SensorState {
+            published,
+            trends,
+            samples: statistics,
+            json: context.json.lock().unwrap().clone(),
+        }
+    }
+
+    /// Handlers for the sensor's own state topics, taking retained state from the last run as
+    /// the last published values of numeric metrics that nothing was restored for, until `until`
+    pub fn warm_start(&self, until: Instant) -> Vec<(String, Handler)> {
+        let sensor = &self.context.sensor;
+        let metrics: Vec<Metric> = sensor
+            .metrics
+            .iter()
+            .copied()
+            .filter(|metric| !metric.is_text())
+            .collect();
+
+        if sensor.output_format == OutputFormat::Json {
+            let context = self.context.clone();
+            let handler: Handler = Box::new(move |payload| {
+                if Instant::now() > until {
+                    return None;
+                }
+                let state = String::from_utf8(payload).ok()?;
+                let Ok(serde_json::Value::Object(object)) = serde_json::from_str(&state) else {
+                    return None;
+                };
+                for &metric in &metrics {
+                    if let Some(value) = object.get(metric.name()).and_then(|v| v.as_f64()) {
+                        context.warm_start(metric, value, &state);
+                    }
+                }
+                context.json.lock().unwrap().get_or_insert(state);
+                None
+            });
+            return vec![(sensor.json_state_topic(), handler)];
+        }
+
+        metrics
+            .into_iter()
+            .map(|metric| {
+                let context = self.context.clone();
+                let handler: Handler = Box::new(move |payload| {
+                    if Instant::now() > until {
+                        return None;
+                    }
+                    let state = String::from_utf8(payload).ok()?;
+                    // timestamped state is wrapped in an object
+                    let value = match serde_json::from_str(&state).ok()? {
+                        serde_json::Value::Object(object) => object.get("value")?.as_f64(),
+                        value => value.as_f64(),
+                    }?;
+                    context.warm_start(metric, value, &state);
+                    None
+                });
+                (sensor.state_topic(metric), handler)
+            })
+            .collect()
+    }
+
+    /// Stops the sensor's timers, returning their thread to wait for if it's running
+    pub fn stop(self) -> Option<JoinHandle<()>> {
+        self.timers.map(|(_, thread)| thread)
+    }
+}
+
+/// Handlers for the sensor's subscriptions, picking up from `restored` state if there is any.
+/// `simulated` source handlers read the plain JSON or numbers `--simulate` feeds them, whatever
+/// the sensor's preset, encoding and compression
+pub fn handlers(
+    sensor: &config::Sensor,
+    tx: &Sender,
+    spans: bool,
+    simulated: bool,
+    restored: Option<SensorState>,
+) -> Result<Subscriptions, Box<dyn Error>> {
+    let context = Arc::new(Context {
+        sensor: sensor.clone(),
+        tx: tx.clone(),
+        offsets: Mutex::new((sensor.temperature_offset, sensor.humidity_offset)),
+        pending: Mutex::default(),
+        pressure: Mutex::default(),
+        surface_temperature: Mutex::default(),
+        spike_filter: Mutex::default(),
+        smoothed: Mutex::default(),
+        published: Mutex::default(),
+        trends: Mutex::default(),
+        samples: Mutex::default(),
+        range_warning: Mutex::default(),
+        passthrough: Mutex::default(),
+        #[cfg(feature = "script")]
+        script: sensor.script.as_deref().map(Script::load).transpose()?,
+        #[cfg(feature = "expressions")]
+        expressions: sensor
+            .expressions
+            .iter()
+            .map(|expression| Ok((expression.clone(), expression.compile()?)))
+            .collect::<Result<_, String>>()?,
+        #[cfg(feature = "expressions")]
+        fields: Mutex::default(),
+        json: Mutex::default(),
+        last_seen: Mutex::default(),
+        online: Mutex::default(),
+        source_online: Mutex::default(),
+        spans,
+    });
+    if let Some(state) = restored {
+        context.restore(state);
+    }
+
+    let mut handlers = match sensor.source()? {
+        Source::Combined(topic) => vec![(
+            topic.to_string(),
+            calculate_dewpoint(topic.to_string(), context.clone()),
+        )],
+        Source::Split {
+            temperature,
+            humidity,
+        } => vec![
+            (
+                temperature.to_string(),
+                split_handler(temperature.to_string(), Field::Temperature, context.clone()),
+            ),
+            (
+                humidity.to_string(),
+                split_handler(humidity.to_string(), Field::Humidity, context.clone()),
+            ),
+        ],
+    };
+    if let Some(preset) = sensor.preset.filter(|_| !simulated) {
+        handlers = handlers
+            .into_iter()
+            .map(|(topic, handler)| {
+                let handler = normalized(handler, topic.clone(), preset, context.clone());
+                (topic, handler)
+            })
+            .collect();
+    }
+    if sensor.payload_encoding != PayloadEncoding::Json && !simulated {
+        let encoding = sensor.payload_encoding;
+        handlers = handlers
+            .into_iter()
+            .map(|(topic, handler)| {
+                let handler = decoded(handler, topic.clone(), encoding, context.clone());
+                (topic, handler)
+            })
+            .collect();
+    }
+    if !simulated {
+        handlers = handlers
+            .into_iter()
+            .map(|(topic, handler)| {
+                let compression = sensor
+                    .compression
+                    .or_else(|| Compression::from_topic(&topic));
+                let handler = match compression {
+                    Some(compression) => {
+                        decompressed(handler, topic.clone(), compression, context.clone())
+                    }
+                    None => handler,
+                };
+                (topic, handler)
+            })
+            .collect();
+    }
+    // sources that never send anything go stale too
+    let now = Instant::now();
+    context
+        .last_seen
+        .lock()
+        .unwrap()
+        .extend(handlers.iter().map(|(topic, _)| (topic.clone(), now)));
+    if sensor.source_availability {
+        let sources: Vec<String> = handlers.iter().map(|(topic, _)| topic.clone()).collect();
+        for source in sources {
+            let topic = format!("{source}/availability");
+            handlers.push((
+                topic.clone(),
+                source_availability_handler(topic, source, context.clone()),
+            ));
+        }
+    }
+    let mut timers = None;
+    if sensor.max_publish_interval().is_some() || sensor.availability_topic.is_some() {
+        let context = context.clone();
+        let (stop, stopped) = mpsc::channel();
+        timers = Some((stop, thread::spawn(move || context.run_timers(&stopped))));
+    }
+    if let Some(topic) = &sensor.pressure_topic {
+        handlers.push((
+            topic.clone(),
+            pressure_handler(topic.clone(), context.clone()),
+        ));
+    }
+    if let Some(topic) = &sensor.surface_temperature_topic {
+        handlers.push((
+            topic.clone(),
+            surface_temperature_handler(topic.clone(), context.clone()),
+        ));
+    }
+
+    let mut commands = Vec::new();
+    if sensor.tunable_offsets {
+        for (field, ..) in OFFSETS {
+            context.publish_offset(field);
+            let topic = sensor.command_topic(field.offset_key());
+            commands.push((topic.clone(), offset_handler(topic, field, context.clone())));
+        }
+    }
+
+    let sources = handlers
+        .into_iter()
+        .map(|(topic, handler)| (topic, timed(handler, context.clone())))
+        .collect();
+    Ok(Subscriptions {
+        sources,
+        commands,
+        handle: Handle { context, timers },
+    })
+}
+
+/// Wraps `handler` to report how long it takes with each message
+fn timed(handler: Handler, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        let started = Instant::now();
+        let response = handler(payload);
+        let _ = context.tx.send(Event::Handled {
+            sensor_id: context.sensor.id().to_string(),
+            duration: started.elapsed(),
+        });
+        response
+    })
+}
+
+/// Wraps `handler` to read each payload with a firmware preset first, skipping any that don't match
+fn normalized(handler: Handler, topic: String, preset: Preset, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| match preset.normalize(&payload) {
+        Ok(payload) => handler(payload),
+        Err(e) => {
+            context.skip(&topic, format!("Skipping payload on {topic}: {e}"));
+            None
+        }
+    })
+}
+
+/// Wraps `handler` to re-encode each payload as JSON first, skipping any that can't be decoded
+fn decoded(
+    handler: Handler,
+    topic: String,
+    encoding: PayloadEncoding,
+    context: Arc<Context>,
+) -> Handler {
+    Box::new(move |payload| match encoding.to_json(&payload) {
+        Ok(payload) => handler(payload),
+        Err(e) => {
+            context.skip(&topic, format!("Skipping payload on {topic}: {e}"));
+            None
+        }
+    })
+}
+
+/// Wraps `handler` to decompress each payload first, skipping any that can't be
+fn decompressed(
+    handler: Handler,
+    topic: String,
+    compression: Compression,
+    context: Arc<Context>,
+) -> Handler {
+    Box::new(move |payload| match compression.decompress(&payload) {
+        Ok(payload) => handler(payload),
+        Err(e) => {
+            context.skip(&topic, format!("Skipping payload on {topic}: {e}"));
+            None
+        }
+    })
+}
+
+fn calculate_dewpoint(topic: String, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        let received = Instant::now();
+        context.seen(&topic);
+        context.update_fields(&payload);
+        #[cfg(feature = "script")]
+        context.run_script(&payload);
+        // zigbee2mqtt also publishes payloads without readings, e.g. for button presses
+        let r: SensorRecord = match serde_json::from_slice(&payload) {
+            Ok(r) => r,
+            Err(e) => {
+                debug!(topic = topic.as_str(); "Skipping payload on {topic}: {e}");
+                return None;
+            }
+        };
+
+        let unit = r.unit.unwrap_or(context.sensor.input_unit);
+        context.publish_metrics(
+            &topic,
+            unit.to_celsius(r.temperature),
+            r.humidity,
+            r.pressure,
+            Duration::ZERO,
+            received,
+        );
+        None
+    })
+}
+
+/// Handler for one half of a split sensor.
+///
+/// Each half caches its value and publishes metrics once the other has a value no older than the
+/// sensor's `max_age`.
+fn split_handler(topic: String, field: Field, context: Arc<Context>) -> Handler {
+    let max_age = context.sensor.max_age();
+    Box::new(move |payload| {
+        let now = Instant::now();
+        context.seen(&topic);
+        context.update_fields(&payload);
+        #[cfg(feature = "script")]
+        context.run_script(&payload);
+        let value = match field {
+            Field::Temperature => context.parse_temperature(&topic, &payload)?,
+            Field::Humidity => context.parse_value(&topic, &payload, field.key())?,
+        };
+
+        let mut pending = context.pending.lock().unwrap();
+        match field {
+            Field::Temperature => pending.temperature = Some((value, now)),
+            Field::Humidity => pending.humidity = Some((value, now)),
+        }
+
+        let (Some((t, t_at)), Some((rh, rh_at))) = (pending.temperature, pending.humidity) else {
+            return None;
+        };
+        drop(pending);
+        if now.duration_since(t_at) > max_age || now.duration_since(rh_at) > max_age {
+            return None;
+        }
+
+        let age = now.duration_since(t_at.min(rh_at));
+        context.publish_metrics(&topic, t, rh, None, age, now);
+        None
+    })
+}
+
+fn pressure_handler(topic: String, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        if let Some(pressure) = context.parse_value(&topic, &payload, "pressure") {
+            *context.pressure.lock().unwrap() = Some(pressure);
+        }
+        None
+    })
+}
+
+fn surface_temperature_handler(topic: String, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        if let Some(temperature) = context.parse_temperature(&topic, &payload) {
+            *context.surface_temperature.lock().unwrap() = Some(temperature);
+        }
+        None
+    })
+}
+
+/// Handler for zigbee2mqtt's availability of a source device, either `online`/`offline` or a JSON
+/// object with a `state` field
+fn source_availability_handler(topic: String, source: String, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        let state = match serde_json::from_slice::<serde_json::Value>(&payload) {
+            Ok(serde_json::Value::Object(object)) => object
+                .get("state")
+                .and_then(serde_json::Value::as_str)
+                .map(String::from),
+            _ => String::from_utf8(payload).ok(),
+        };
+        let online = match state.as_deref().map(str::trim) {
+            Some("online") => true,
+            Some("offline") => false,
+            _ => {
+                context.skip(&topic, format!("Skipping availability on {topic}"));
+                return None;
+            }
+        };
+        context
+            .source_online
+            .lock()
+            .unwrap()
+            .insert(source.clone(), online);
+        context.set_online(!context.is_stale());
+        None
+    })
+}
+
+/// Handler for new values of a `number` entity's offset. Commands are retained, so the last one
+/// is also applied after a restart.
+fn offset_handler(topic: String, field: Field, context: Arc<Context>) -> Handler {
+    Box::new(move |payload| {
+        let offset = context.parse_value(&topic, &payload, "value")?;
+        let max = field.max_offset();
+        if offset.abs() > max {
+            context.skip(
+                &topic,
+                format!("Skipping offset {offset} on {topic} outside -{max} to {max}"),
+            );
+            return None;
+        }
+        let mut offsets = context.offsets.lock().unwrap();
+        match field {
+            Field::Temperature => offsets.0 = offset,
+            Field::Humidity => offsets.1 = offset,
+        }
+        drop(offsets);
+        info!(
+            "{} {} set to {offset}",
+            context.sensor.id(),
+            field.offset_key()
+        );
+        context.publish_offset(field);
+        None
+    })
+}
+
+impl Context {
+    /// Runs the sensor's heartbeat and staleness checks until `stopped`'s sender is dropped
+    fn run_timers(&self, stopped: &mpsc::Receiver<()>) {
+        loop {
+            let mut next = Duration::MAX;
+            if let Some(interval) = self.sensor.max_publish_interval() {
+                next = next.min(self.heartbeat(interval));
+            }
+            if self.sensor.availability_topic.is_some() {
+                next = next.min(self.check_stale());
+            }
+            if stopped.recv_timeout(next) != Err(RecvTimeoutError::Timeout) {
+                return;
+            }
+        }
+    }
+
+    /// Republishes metrics that haven't been published within `interval`, returning how long
+    /// until the next one is due
+    fn heartbeat(&self, interval: Duration) -> Duration {
+        // republishing would hide that the values are stale
+        if *self.online.lock().unwrap() == Some(false) {
+            return interval;
+        }
+
+        let mut next = interval;
+        let mut published = self.published.lock().unwrap();
+        let mut due = Vec::new();
+        for (metric, last) in published.iter_mut() {
+            let elapsed = last.at.elapsed();
+            if elapsed < interval {
+                next = next.min(interval - elapsed);
+                continue;
+            }
+            due.push(Value {
+                key: metric.name().to_string(),
+                component: metric.component(),
+                payload: last.state.clone(),
+            });
+            last.at = Instant::now();
+        }
+
+        if self.sensor.output_format == OutputFormat::Json && !due.is_empty() {
+            // the JSON payload has every metric, so they're all republished together
+            for last in published.values_mut() {
+                last.at = Instant::now();
+            }
+            next = interval;
+            if let Some(payload) = self.json.lock().unwrap().clone() {
+                self.send("state", "sensor", payload);
+            }
+        } else {
+            for value in due {
+                self.send(&value.key, value.component, value.payload);
+            }
+        }
+        next
+    }
+
+    /// Picks up where a previous run left off, ignoring metrics the sensor no longer publishes and
+    /// samples from before the monotonic clock started
+    fn restore(&self, state: SensorState) {
+        let metric = |name: &str| {
+            self.sensor
+                .metrics
+                .iter()
+                .copied()
+                .find(|metric| metric.name() == name)
+        };
+        let samples = |values: checkpoint::Samples| -> Samples {
+            values
+                .into_iter()
+                .filter_map(|(at, value)| Some((checkpoint::instant(at)?, value)))
+                .collect()
+        };
+
+        *self.published.lock().unwrap() = state
+            .published
+            .into_iter()
+            .filter_map(|(name, last)| {
+                let published = Published {
+                    value: last.value,
+                    payload: last.payload,
+                    state: last.state,
+                    at: checkpoint::instant(last.at)?,
+                };
+                Some((metric(&name)?, published))
+            })
+            .collect();
+        *self.trends.lock().unwrap() = (samples(state.trends.0), samples(state.trends.1));
+        *self.samples.lock().unwrap() = state
+            .samples
+            .into_iter()
+            .filter_map(|(name, values)| Some((metric(&name)?, samples(values))))
+            .collect();
+        if self.sensor.output_format == OutputFormat::Json {
+            *self.json.lock().unwrap() = state.json;
+        }
+    }
+
+    /// Takes retained state as a metric's last published value, unless there already is one
+    fn warm_start(&self, metric: Metric, value: f64, state: &str) {
+        self.published
+            .lock()
+            .unwrap()
+            .entry(metric)
+            .or_insert_with(|| Published {
+                value,
+                payload: metric.payload(value, &self.sensor),
+                state: state.to_string(),
+                at: Instant::now(),
+            });
+    }
+
+    /// Publishes a state payload to each of the sensor's outputs
+    fn send(&self, key: &str, component: &str, payload: String) {
+        for (topic, broker, retain) in self.sensor.outputs_for(key, component) {
+            // the receiver only goes away during shutdown, when there's no one left to publish to
+            let _ = self.tx.send(Event::Publish(Message {
+                topic,
+                payload: payload.clone(),
+                retain,
+                broker: broker.map(String::from),
+            }));
+        }
+    }
+
+    /// Records a message on one of the sensor's source topics, publishing the sensor as online
+    /// once none of them are stale
+    fn seen(&self, topic: &str) {
+        let _ = self.tx.send(Event::Received {
+            sensor_id: self.sensor.id().to_string(),
+        });
+        self.last_seen
+            .lock()
+            .unwrap()
+            .insert(topic.to_string(), Instant::now());
+        if !self.is_stale() {
+            self.set_online(true);
+        }
+    }
+
+    /// Whether any source topic has been quiet for longer than `stale_after`
+    fn is_stale(&self) -> bool {
+        let stale_after = self.sensor.stale_after();
+        self.last_seen
+            .lock()
+            .unwrap()
+            .values()
+            .any(|at| at.elapsed() > stale_after)
+    }
+
+    /// Publishes the sensor as offline once a source topic has been quiet for `stale_after`,
+    /// returning how long until that could next happen
+    fn check_stale(&self) -> Duration {
+        let stale_after = self.sensor.stale_after();
+        let quiet = self
+            .last_seen
+            .lock()
+            .unwrap()
+            .values()
+            .map(Instant::elapsed)
+            .max()
+            .unwrap_or_default();
+        if quiet > stale_after {
+            self.set_online(false);
+            stale_after
+        } else {
+            stale_after - quiet
+        }
+    }
+
+    /// Publishes the sensor's availability if it changed, which is offline regardless of `online`
+    /// while a source device is reported offline
+    fn set_online(&self, online: bool) {
+        let Some(topic) = self.sensor.availability_topic() else {
+            return;
+        };
+        let online = online && self.source_online.lock().unwrap().values().all(|&on| on);
+        let mut current = self.online.lock().unwrap();
+        if *current == Some(online) {
+            return;
+        }
+        *current = Some(online);
+        drop(current);
+
+        info!(
+            "{} is {}",
+            self.sensor.id(),
+            if online { "online" } else { "offline" }
+        );
+        let _ = self.tx.send(Event::Publish(Message {
+            topic,
+            payload: String::from(if online { "online" } else { "offline" }),
+            retain: true,
+            broker: None,
+        }));
+    }
+
+    /// Caches the sensor's `passthrough` fields from a source payload to publish with its metrics,
+    /// and its numeric fields for expressions
+    fn update_fields(&self, payload: &[u8]) {
+        #[cfg(feature = "expressions")]
+        let expressions = !self.expressions.is_empty();
+        #[cfg(not(feature = "expressions"))]
+        let expressions = false;
+        if self.sensor.passthrough.is_empty() && !expressions {
+            return;
+        }
+        let Ok(serde_json::Value::Object(fields)) = serde_json::from_slice(payload) else {
+            return;
+        };
+
+        #[cfg(feature = "expressions")]
+        if expressions {
+            self.fields.lock().unwrap().extend(
+                fields
+                    .iter()
+                    .filter_map(|(field, value)| Some((field.clone(), value.as_f64()?))),
+            );
+        }
+
+        let mut passthrough = self.passthrough.lock().unwrap();
+        for field in &self.sensor.passthrough {
+            if let Some(value) = fields.get(field) {
+                let payload = value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), String::from);
+                passthrough.insert(field.clone(), payload);
+            }
+        }
+    }
+
+    /// Logs an error, also reporting it as the daemon's last error
+    fn error(&self, message: String) {
+        error!("{message}");
+        let _ = self.tx.send(Event::Error {
+            sensor_id: self.sensor.id().to_string(),
+            topic: None,
+            message,
+        });
+    }
+
+    /// Logs a message that couldn't be used as a warning, also reporting it as an error
+    fn skip(&self, topic: &str, message: String) {
+        warn!(topic = topic; "{message}");
+        let _ = self.tx.send(Event::Error {
+            sensor_id: self.sensor.id().to_string(),
+            topic: Some(topic.to_string()),
+            message,
+        });
+    }
+
+    /// Accepts either a bare number or a JSON object containing `key`, reporting anything else
+    fn parse_value(&self, topic: &str, payload: &[u8], key: &str) -> Option<f64> {
+        let v: serde_json::Value = match serde_json::from_slice(payload) {
+            Ok(v) => v,
+            Err(e) => {
+                self.skip(topic, format!("Skipping payload on {topic}: {e}"));
+                return None;
+            }
+        };
+        let value = v
+            .as_f64()
+            .or_else(|| v.get(key).and_then(serde_json::Value::as_f64));
+        if value.is_none() {
+            self.skip(topic, format!("Skipping payload on {topic} without {key}"));
+        }
+        value
+    }
+
+    /// A temperature in °C from `parse_value`, in the payload's `unit` if it's a JSON object with
+    /// one and otherwise the sensor's `input_unit`
+    fn parse_temperature(&self, topic: &str, payload: &[u8]) -> Option<f64> {
+        let value = self.parse_value(topic, payload, "temperature")?;
+        let unit = serde_json::from_slice::<serde_json::Value>(payload)
+            .ok()
+            .and_then(|v| v.get("unit").cloned())
+            .and_then(|unit| Unit::deserialize(unit).ok())
+            .unwrap_or(self.sensor.input_unit);
+        Some(unit.to_celsius(value))
+    }
+
+    /// Publishes the current value of one of the sensor's offsets, retained
+    fn publish_offset(&self, field: Field) {
+        let offsets = *self.offsets.lock().unwrap();
+        let offset = match field {
+            Field::Temperature => offsets.0,
+            Field::Humidity => offsets.1,
+        };
+        let _ = self.tx.send(Event::Publish(Message {
+            topic: self.sensor.state_topic_for(field.offset_key(), "number"),
+            payload: offset.to_string(),
+            retain: true,
+            broker: None,
+        }));
+    }
+
+    /// Publishes the sensor's range warning when it changes, if enabled
+    fn publish_range_warning(&self, warning: bool) {
+        if !self.sensor.range_warning {
+            return;
+        }
+        let mut last = self.range_warning.lock().unwrap();
+        if *last == Some(warning) {
+            return;
+        }
+        *last = Some(warning);
+        drop(last);
+
+        let payload = String::from(if warning { "ON" } else { "OFF" });
+        self.send(RANGE_WARNING.0, RANGE_WARNING.1, payload);
+    }
+
+    /// Publishes whatever the sensor's script outputs for a source payload
+    #[cfg(feature = "script")]
+    fn run_script(&self, payload: &[u8]) {
+        let Some(script) = &self.script else {
+            return;
+        };
+        let outputs = std::str::from_utf8(payload)
+            .map_err(Into::into)
+            .and_then(|payload| script.process(payload));
+        match outputs {
+            Ok(outputs) => {
+                for (topic, payload) in outputs {
+                    let _ = self.tx.send(Event::Publish(Message {
+                        topic,
+                        payload,
+                        retain: self.sensor.retain(),
+                        broker: None,
+                    }));
+                }
+            }
+            Err(e) => self.error(format!(
+                "Error running script for {}: {e}",
+                self.sensor.id()
+            )),
+        }
+    }
+
+    #[cfg(feature = "expressions")]
+    fn evaluate_expressions(&self, reading: &Reading, dewpoint: f64) -> Vec<Value> {
+        if self.expressions.is_empty() {
+            return Vec::new();
+        }
+        let fields = self.fields.lock().unwrap();
+        let variables = Variables {
+            temperature: reading.temperature,
+            humidity: reading.humidity,
+            pressure: reading.pressure,
+            dewpoint,
+            fields: &fields,
+        };
+        let context =
+            match expression::context(&variables, self.sensor.dewpoint_formula, self.sensor.magnus)
+            {
+                Ok(context) => context,
+                Err(e) => {
+                    self.error(format!(
+                        "Error preparing expressions for {}: {e}",
+                        self.sensor.id()
+                    ));
+                    return Vec::new();
+                }
+            };
+
+        let mut values = Vec::new();
+        for (expression, node) in &self.expressions {
+            match node.eval_number_with_context(&context) {
+                Ok(value) => {
+                    let payload = format!("{value:.*}", expression.precision);
+                    debug!(target: "app::dewpoint", "{}: {payload}", expression.label());
+                    values.push(Value {
+                        key: expression.name.clone(),
+                        component: "sensor",
+                        payload,
+                    });
+                }
+                Err(e) => {
+                    self.error(format!(
+                        "Error evaluating expression {}: {e}",
+                        expression.name
+                    ));
+                }
+            }
+        }
+        values
+    }
+
+    /// Records a numeric metric's value, returning its statistics to publish alongside it
+    fn update_statistics(&self, metric: Metric, value: f64) -> Vec<Value> {
+        let Some(keep) = self.sensor.statistics_window() else {
+            return Vec::new();
+        };
+        if metric.is_text() {
+            return Vec::new();
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(metric).or_default();
+        samples.push(value, keep);
+
+        let mut values = Vec::new();
+        for &hours in &self.sensor.statistics {
+            for statistic in Statistic::ALL {
+                let value = samples.calculate(statistic, Duration::from_secs(hours * 3600));
+                let key = statistics::name(metric, statistic, hours);
+                values.push(Value {
+                    key,
+                    component: metric.component(),
+                    payload: format!("{value:.*}", metric.precision()),
+                });
+            }
+        }
+        values
+    }
+
+    /// Calculates and publishes every metric of the sensor. Pressure from the sensor's own payload
+    /// takes precedence over `pressure_topic`, which takes precedence over the configured pressure.
+    /// `age` is how old the oldest value in the reading is, and `received` when the message that
+    /// completed it arrived.
+    fn publish_metrics(
+        &self,
+        source: &str,
+        temperature: f64,
+        humidity: f64,
+        pressure: Option<f64>,
+        age: Duration,
+        received: Instant,
+    ) {
+        let started = SystemTime::now();
+        let sensor = &self.sensor;
+        let raw = (temperature, humidity);
+        let offsets = *self.offsets.lock().unwrap();
+        let (mut temperature, mut humidity) = sensor.calibrate(temperature, humidity, offsets);
+        let range = sensor.temperature_range;
+        let out_of_range = filter::out_of_range(temperature, humidity, range);
+        self.publish_range_warning(out_of_range.is_some());
+        if let Some(reason) = out_of_range {
+            match sensor.out_of_range {
+                OutOfRange::Clamp if temperature.is_finite() && humidity.is_finite() => {
+                    warn!("Clamping reading from {}: {reason}", sensor.id());
+                    (temperature, humidity) = filter::clamp(temperature, humidity, range);
+                }
+                _ => {
+                    self.error(format!("Dropping reading from {}: {reason}", sensor.id()));
+                    return;
+                }
+            }
+        }
+        if let Err(e) = self.spike_filter.lock().unwrap().check(
+            temperature,
+            humidity,
+            sensor.max_temperature_jump,
+            sensor.max_humidity_jump,
+        ) {
+            self.error(format!("Dropping reading from {}: {e}", sensor.id()));
+            return;
+        }
+        if let Some(smoothing) = sensor.smoothing {
+            let mut smoothed = self.smoothed.lock().unwrap();
+            temperature = smoothed.0.update(smoothing, temperature);
+            humidity = smoothed.1.update(smoothing, humidity);
+        }
+
+        info!(
+            target: "app::dewpoint",
+            "Temp: {:.2}°C / {:.2}°F - Hum: {}%",
+            temperature,
+            temperature.mul_add(1.8, 32_f64),
+            humidity
+        );
+
+        let dewpoint = sensor
+            .dewpoint_formula
+            .dewpoint(sensor.magnus, temperature, humidity);
+        let mut trends = self.trends.lock().unwrap();
+        trends.0.push(temperature, sensor.trend_window());
+        trends.1.push(dewpoint, sensor.trend_window());
+        let (temperature_trend, dewpoint_trend) = (trends.0.slope(), trends.1.slope());
+        drop(trends);
+
+        let reading = Reading {
+            temperature,
+            humidity,
+            pressure: pressure
+                .or(*self.pressure.lock().unwrap())
+                .unwrap_or_else(|| sensor.pressure()),
+            surface_temperature: *self.surface_temperature.lock().unwrap(),
+            temperature_trend,
+            dewpoint_trend,
+        };
+        let mut calculated = Vec::new();
+        for &metric in &sensor.metrics {
+            let Some(value) = metric.calculate(&reading, sensor) else {
+                continue;
+            };
+            let payload = metric.payload(value, sensor);
+            let unit = metric
+                .unit_of_measurement(sensor.output_unit)
+                .unwrap_or_default();
+
+            debug!(target: "app::dewpoint", "{}: {payload}{unit}", metric.label());
+
+            let statistics = self.update_statistics(metric, value);
+            let changed = self
+                .published
+                .lock()
+                .unwrap()
+                .get(&metric)
+                .is_none_or(|last| {
+                    last.at.elapsed() >= sensor.min_publish_interval()
+                        && !sensor.deadband.is_some_and(|deadband| {
+                            if metric.is_text() {
+                                payload == last.payload
+                            } else {
+                                (value - last.value).abs() <= deadband
+                            }
+                        })
+                });
+            calculated.push((metric, value, payload, statistics, changed));
+        }
+
+        let mut history: Vec<(String, f64)> = [
+            ("raw_temperature", raw.0),
+            ("raw_humidity", raw.1),
+            ("temperature", temperature),
+            ("humidity", humidity),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect();
+        history.extend(
+            calculated
+                .iter()
+                .filter(|(metric, ..)| !metric.is_text())
+                .map(|(metric, value, ..)| (metric.name().to_string(), *value)),
+        );
+
+        // JSON payloads have every metric, so any change publishes all of them
+        let json = sensor.output_format == OutputFormat::Json;
+        let updated = sensor.timestamps.then(|| Updated {
+            last_updated: timestamp::now(),
+            source: source.to_string(),
+        });
+        let publish_all = json && calculated.iter().any(|(.., changed)| *changed);
+        let mut values = Vec::new();
+        let mut published = self.published.lock().unwrap();
+        for (metric, value, payload, statistics, changed) in calculated {
+            if !(changed || publish_all) {
+                continue;
+            }
+            published.insert(
+                metric,
+                Published {
+                    value,
+                    state: state_payload(payload.clone(), updated.as_ref()),
+                    payload: payload.clone(),
+                    at: Instant::now(),
+                },
+            );
+            values.push(Value {
+                key: metric.name().to_string(),
+                component: metric.component(),
+                payload,
+            });
+            values.extend(statistics);
+        }
+        drop(published);
+
+        if !(json && values.is_empty()) {
+            #[cfg(feature = "expressions")]
+            values.extend(self.evaluate_expressions(&reading, dewpoint));
+            let passthrough = self.passthrough.lock().unwrap();
+            values.extend(passthrough.iter().map(|(field, payload)| Value {
+                key: field.clone(),
+                component: "sensor",
+                payload: payload.clone(),
+            }));
+        }
+
+        let computed = SystemTime::now();
+        if json {
+            if !values.is_empty() {
+                let payload = json_payload(values, updated.as_ref());
+                *self.json.lock().unwrap() = Some(payload.clone());
+                self.send("state", "sensor", payload);
+            }
+        } else {
+            for value in values {
+                let payload = state_payload(value.payload, updated.as_ref());
+                self.send(&value.key, value.component, payload);
+            }
+        }
+
+        if let Some(topic) = sensor.attributes_topic() {
+            let attributes = serde_json::json!({
+                "source": source,
+                "raw_temperature": raw.0,
+                "raw_humidity": raw.1,
+                "temperature": temperature,
+                "humidity": humidity,
+                "pressure": reading.pressure,
+                "dewpoint_formula": sensor.dewpoint_formula.name(),
+                "sample_age": age.as_secs_f64(),
+            });
+            let _ = self.tx.send(Event::Publish(Message {
+                topic,
+                payload: attributes.to_string(),
+                retain: sensor.retain(),
+                broker: None,
+            }));
+        }
+
+        let _ = self.tx.send(Event::Reading {
+            sensor_id: sensor.id().to_string(),
+            latest: Latest {
+                temperature,
+                humidity,
+                dewpoint,
+            },
+            received,
+            values: history,
+        });
+        if self.spans {
+            self.send_spans(source, started, computed);
+        }
+    }
+
+    /// Sends a span covering a reading's processing, with children for calculating its values and
+    /// handing them off to be published
+    #[cfg(feature = "otlp")]
+    fn send_spans(&self, source: &str, started: SystemTime, computed: SystemTime) {
+        let trace_id = u128::from(otlp::id()) << 64 | u128::from(otlp::id());
+        let parent_id = otlp::id();
+        let attributes = vec![
+            ("sensor.id", self.sensor.id().to_string()),
+            ("messaging.destination.name", source.to_string()),
+        ];
+        let spans = [
+            ("reading", parent_id, None, started, SystemTime::now()),
+            ("compute", otlp::id(), Some(parent_id), started, computed),
+            (
+                "publish",
+                otlp::id(),
+                Some(parent_id),
+                computed,
+                SystemTime::now(),
+            ),
+        ];
+        for (name, span_id, parent_id, start, end) in spans {
+            let _ = self.tx.send(Event::Span(Span {
+                name,
+                trace_id,
+                span_id,
+                parent_id,
+                start,
+                end,
+                attributes: attributes.clone(),
+            }));
+        }
+    }
+
+    /// Spans are only collected for export over OTLP
+    #[cfg(not(feature = "otlp"))]
+    #[allow(clippy::unused_self)]
+    fn send_spans(&self, _: &str, _: SystemTime, _: SystemTime) {}
+}
+
+/// Topics and payloads of the Home Assistant discovery configs for each of a sensor's metrics,
+/// their statistics, and passed through fields
+pub fn discovery_configs(sensor: &config::Sensor, daemon: Option<&str>) -> Vec<(String, String)> {
+    let mut configs = Vec::new();
+    for &metric in &sensor.metrics {
+        if let Some(topic) = sensor.discovery_topic(metric) {
+            let payload = discovery_payload(
+                sensor,
+                metric.label(),
+                metric.name(),
+                &sensor.state_topic(metric),
+                metric_config(sensor, metric),
+                daemon,
+            );
+            configs.push((topic, payload));
+        }
+        if metric.is_text() {
+            continue;
+        }
+        for &hours in &sensor.statistics {
+            for statistic in Statistic::ALL {
+                let key = statistics::name(metric, statistic, hours);
+                if let Some(topic) = sensor.discovery_topic_for(&key, metric.component()) {
+                    let payload = discovery_payload(
+                        sensor,
+                        &statistics::label(metric, statistic, hours),
+                        &key,
+                        &sensor.state_topic_for(&key, metric.component()),
+                        metric_config(sensor, metric),
+                        daemon,
+                    );
+                    configs.push((topic, payload));
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "expressions")]
+    for expression in &sensor.expressions {
+        let Some(topic) = sensor.discovery_topic_for(&expression.name, "sensor") else {
+            continue;
+        };
+        let payload = discovery_payload(
+            sensor,
+            expression.label(),
+            &expression.name,
+            &sensor.state_topic_for(&expression.name, "sensor"),
+            serde_json::json!({
+                "device_class": expression.device_class,
+                "state_class": "measurement",
+                "unit_of_measurement": expression.unit_of_measurement,
+                "suggested_display_precision": expression.precision,
+                "expire_after": sensor.expire_after,
+            }),
+            daemon,
+        );
+        configs.push((topic, payload));
+    }
+
+    if sensor.range_warning {
+        if let Some(topic) = sensor.discovery_topic_for(RANGE_WARNING.0, RANGE_WARNING.1) {
+            // published to its own topic even when output_format is json
+            let payload = serde_json::json!({
+                "name": format!("{} Range warning", sensor.name()),
+                "unique_id": discovery::unique_id(sensor.id(), RANGE_WARNING.0),
+                "device": discovery::device(sensor.id(), sensor.name()),
+                "device_class": "problem",
+                "entity_category": "diagnostic",
+                "state_topic": sensor.state_topic_for(RANGE_WARNING.0, RANGE_WARNING.1),
+                "availability_topic": sensor.availability_topic(),
+            });
+            configs.push((topic, discovery::payload(payload, daemon)));
+        }
+    }
+
+    if sensor.tunable_offsets {
+        for (field, label, unit) in OFFSETS {
+            let key = field.offset_key();
+            let max = field.max_offset();
+            let Some(topic) = sensor.discovery_topic_for(key, "number") else {
+                continue;
+            };
+            // published to its own topic even when output_format is json
+            let payload = serde_json::json!({
+                "name": format!("{} {label}", sensor.name()),
+                "unique_id": discovery::unique_id(sensor.id(), key),
+                "device": discovery::device(sensor.id(), sensor.name()),
+                "entity_category": "config",
+                "state_topic": sensor.state_topic_for(key, "number"),
+                "command_topic": sensor.command_topic(key),
+                "retain": true,
+                "min": -max,
+                "max": max,
+                "step": 0.1,
+                "mode": "box",
+                "unit_of_measurement": unit,
+                "availability_topic": sensor.availability_topic(),
+            });
+            configs.push((topic, discovery::payload(payload, daemon)));
+        }
+    }
+
+    for field in &sensor.passthrough {
+        let Some(topic) = sensor.discovery_topic_for(field, "sensor") else {
+            continue;
+        };
+        let known = KNOWN_FIELDS.iter().find(|(name, ..)| name == field);
+        let (label, device_class, unit) = known.map_or(
+            (field.as_str(), None, None),
+            |&(_, label, device_class, unit)| (label, device_class, unit),
+        );
+        let payload = discovery_payload(
+            sensor,
+            label,
+            field,
+            &sensor.state_topic_for(field, "sensor"),
+            serde_json::json!({
+                "device_class": device_class,
+                // unknown fields may not be numeric
+                "state_class": known.map(|_| "measurement"),
+                "unit_of_measurement": unit,
+                "entity_category": "diagnostic",
+            }),
+            daemon,
+        );
+        configs.push((topic, payload));
+    }
+    configs
+}
+
+fn metric_config(sensor: &config::Sensor, metric: Metric) -> serde_json::Value {
+    let numeric = !metric.is_text();
+    let mut config = serde_json::json!({
+        "device_class": metric.device_class(),
+        "state_class": numeric.then_some("measurement"),
+        "unit_of_measurement": metric.unit_of_measurement(sensor.output_unit),
+        "suggested_display_precision": numeric.then(|| metric.precision()),
+        "icon": metric.icon(),
+        "expire_after": sensor.expire_after,
+        "options": metric.options(sensor),
+    });
+    if let Some(overrides) = sensor.discovery_options.get(metric.name()) {
+        apply_overrides(overrides, &mut config);
+    }
+    config
+}
+
+/// Replaces options in a discovery config with those set in the sensor's config
+fn apply_overrides(overrides: &config::DiscoveryOptions, config: &mut serde_json::Value) {
+    let overrides = serde_json::json!({
+        "icon": overrides.icon,
+        "device_class": overrides.device_class,
+        "unit_of_measurement": overrides.unit_of_measurement,
+        "expire_after": overrides.expire_after,
+        "state_class": overrides.state_class,
+        "suggested_display_precision": overrides.suggested_display_precision,
+    });
+    if let (Some(options), serde_json::Value::Object(overrides)) =
+        (config.as_object_mut(), overrides)
+    {
+        options.extend(overrides.into_iter().filter(|(_, value)| !value.is_null()));
+    }
+}
+
+/// Discovery config for a published value, where `key` is its name in JSON payloads, adding the
+/// options common to every entity to `config`
+fn discovery_payload(
+    sensor: &config::Sensor,
+    label: &str,
+    key: &str,
+    state_topic: &str,
+    mut config: serde_json::Value,
+    daemon: Option<&str>,
+) -> String {
+    let (state_topic, value_template) = match sensor.output_format {
+        OutputFormat::Topics if sensor.timestamps => (
+            state_topic.to_string(),
+            Some(String::from("{{ value_json.value }}")),
+        ),
+        OutputFormat::Topics => (state_topic.to_string(), None),
+        OutputFormat::Json => (
+            sensor.json_state_topic(),
+            Some(format!("{{{{ value_json.{key} }}}}")),
+        ),
+    };
+    if let Some(options) = config.as_object_mut() {
+        let common = serde_json::json!({
+            "name": format!("{} {label}", sensor.name()),
+            "unique_id": discovery::unique_id(sensor.id(), key),
+            "device": discovery::device(sensor.id(), sensor.name()),
+            "state_topic": state_topic,
+            "value_template": value_template,
+            "availability_topic": sensor.availability_topic(),
+            "json_attributes_topic": sensor.attributes_topic(),
+        });
+        if let serde_json::Value::Object(common) = common {
+            options.extend(common);
+        }
+    }
+    if let Some(overrides) = sensor.discovery_options.get(key) {
+        apply_overrides(overrides, &mut config);
+    }
+    discovery::payload(config, daemon)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serde_json::{json, Value};
+
+    const BROKER: &str = r#"
+        broker_addr = "127.0.0.1:1883"
+        client_id = "humidity"
+        username = "humidity"
+        password = ""
+        output_topic = "homeassistant/sensor/{sensor_id}/{metric}/state"
+        discovery_prefix = "homeassistant"
+    "#;
+
+    /// Loads `toml` after the broker settings, as a config file would be
+    fn try_load(name: &str, toml: &str) -> Result<Config, Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("mqtt_dewpoint_{name}.toml"));
+        std::fs::write(&path, format!("{BROKER}{toml}")).unwrap();
+        let config = Config::load(path.to_str().unwrap(), None);
+        let _ = std::fs::remove_file(&path);
+        config
+    }
+
+    fn load(name: &str, toml: &str) -> Config {
+        try_load(name, toml).unwrap()
+    }
+
+    /// Discovery configs of every sensor in a config, by topic, with topics prefixed as they're
+    /// announced
+    fn discovery(name: &str, toml: &str) -> Value {
+        let config = load(&format!("discovery_{name}"), toml);
+
+        let mut configs = serde_json::Map::new();
+        for sensor in &config.sensors {
+            let daemon = config.daemon_availability_topic.as_deref();
+            for (topic, payload) in discovery_configs(sensor, daemon) {
+                let payload = match &config.topic_prefix {
+                    Some(prefix) => discovery::prefix_topics(&payload, prefix),
+                    None => payload,
+                };
+                configs.insert(topic, serde_json::from_str(&payload).unwrap());
+            }
+        }
+        configs.into()
+    }
+
+    fn device(id: &str) -> Value {
+        json!({
+            "identifiers": [format!("mqtt_dewpoint_{id}")],
+            "model": "mqtt_dewpoint",
+            "name": id,
+            "sw_version": env!("CARGO_PKG_VERSION"),
+        })
+    }
+
+    #[test]
+    fn combined_topic() {
+        let configs = discovery(
+            "combined",
+            r#"
+            [[sensor]]
+            topic = "zigbee2mqtt/attic"
+            metrics = ["dewpoint", "comfort"]
+            output_unit = "C"
+            "#,
+        );
+        assert_eq!(
+            configs,
+            json!({
+                "homeassistant/sensor/attic/dewpoint/config": {
+                    "device": device("attic"),
+                    "device_class": "temperature",
+                    "name": "attic Dewpoint",
+                    "state_class": "measurement",
+                    "state_topic": "homeassistant/sensor/attic/dewpoint/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_attic_dewpoint",
+                    "unit_of_measurement": "°C",
+                },
+                "homeassistant/sensor/attic/comfort/config": {
+                    "device": device("attic"),
+                    "device_class": "enum",
+                    "icon": "mdi:emoticon-outline",
+                    "name": "attic Comfort",
+                    "options": ["dry", "comfortable", "muggy", "oppressive"],
+                    "state_topic": "homeassistant/sensor/attic/comfort/state",
+                    "unique_id": "mqtt_dewpoint_attic_comfort",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn split_topics() {
+        let configs = discovery(
+            "split",
+            r#"
+            [[sensor]]
+            id = "basement"
+            temperature_topic = "basement/temperature"
+            humidity_topic = "basement/humidity"
+            "#,
+        );
+        assert_eq!(
+            configs,
+            json!({
+                "homeassistant/sensor/basement/dewpoint/config": {
+                    "device": device("basement"),
+                    "device_class": "temperature",
+                    "name": "basement Dewpoint",
+                    "state_class": "measurement",
+                    "state_topic": "homeassistant/sensor/basement/dewpoint/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_basement_dewpoint",
+                    "unit_of_measurement": "°F",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn preset() {
+        let configs = discovery(
+            "preset",
+            r#"
+            [[sensor]]
+            topic = "living-room"
+            preset = "esphome"
+            "#,
+        );
+        assert_eq!(
+            configs,
+            json!({
+                "homeassistant/sensor/living-room/dewpoint/config": {
+                    "device": device("living-room"),
+                    "device_class": "temperature",
+                    "name": "living-room Dewpoint",
+                    "state_class": "measurement",
+                    "state_topic": "homeassistant/sensor/living-room/dewpoint/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_living-room_dewpoint",
+                    "unit_of_measurement": "°F",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn topic_prefix() {
+        let configs = discovery(
+            "prefix",
+            r#"
+            topic_prefix = "site-a/"
+            daemon_availability_topic = "mqtt_dewpoint/availability"
+            availability_topic = "homeassistant/sensor/{sensor_id}/availability"
+
+            [[sensor]]
+            topic = "zigbee2mqtt/attic"
+            "#,
+        );
+        assert_eq!(
+            configs,
+            json!({
+                "homeassistant/sensor/attic/dewpoint/config": {
+                    "availability": [
+                        {"topic": "site-a/mqtt_dewpoint/availability"},
+                        {"topic": "site-a/homeassistant/sensor/attic/availability"},
+                    ],
+                    "availability_mode": "all",
+                    "device": device("attic"),
+                    "device_class": "temperature",
+                    "name": "attic Dewpoint",
+                    "state_class": "measurement",
+                    "state_topic": "site-a/homeassistant/sensor/attic/dewpoint/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_attic_dewpoint",
+                    "unit_of_measurement": "°F",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        let configs = discovery(
+            "json",
+            r#"
+            [[sensor]]
+            topic = "zigbee2mqtt/attic"
+            metrics = ["dewpoint", "heat_index"]
+            output_topic = "humidity/{sensor_id}/{metric}"
+            output_format = "json"
+            "#,
+        );
+        assert_eq!(
+            configs,
+            json!({
+                "homeassistant/sensor/attic/dewpoint/config": {
+                    "device": device("attic"),
+                    "device_class": "temperature",
+                    "name": "attic Dewpoint",
+                    "state_class": "measurement",
+                    "state_topic": "humidity/attic/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_attic_dewpoint",
+                    "unit_of_measurement": "°F",
+                    "value_template": "{{ value_json.dewpoint }}",
+                },
+                "homeassistant/sensor/attic/heat_index/config": {
+                    "device": device("attic"),
+                    "device_class": "temperature",
+                    "name": "attic Heat index",
+                    "state_class": "measurement",
+                    "state_topic": "humidity/attic/state",
+                    "suggested_display_precision": 1,
+                    "unique_id": "mqtt_dewpoint_attic_heat_index",
+                    "unit_of_measurement": "°F",
+                    "value_template": "{{ value_json.heat_index }}",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn split_temperature_unit() {
+        let config = load(
+            "split_unit",
+            r#"
+            [[sensor]]
+            id = "basement"
+            temperature_topic = "basement/temperature"
+            humidity_topic = "basement/humidity"
+            input_unit = "C"
+            "#,
+        );
+        let (tx, rx) = mpsc::channel();
+        let subscriptions = handlers(&config.sensors[0], &tx, false, false, None).unwrap();
+        for (topic, handler) in &subscriptions.sources {
+            match topic.as_str() {
+                "basement/temperature" => handler(r#"{"temperature":70.7,"unit":"°F"}"#.into()),
+                "basement/humidity" => handler(b"45".to_vec()),
+                _ => None,
+            };
+        }
+
+        let latest = rx
+            .try_iter()
+            .find_map(|event| match event {
+                Event::Reading { latest, .. } => Some(latest),
+                _ => None,
+            })
+            .unwrap();
+        assert!(
+            (latest.temperature - 21.5).abs() < 0.01,
+            "{}",
+            latest.temperature
+        );
+        assert!((latest.humidity - 45.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn colliding_unique_ids() {
+        let sensor = |options: &str| {
+            format!(
+                r#"
+                [[sensor]]
+                id = "attic"
+                topic = "zigbee2mqtt/attic"
+                statistics = [24]
+                {options}
+                "#
+            )
+        };
+        for (name, toml) in [
+            ("metric", sensor(r#"passthrough = ["dewpoint"]"#)),
+            ("statistic", sensor(r#"passthrough = ["dewpoint_max_24h"]"#)),
+            (
+                "passthrough",
+                sensor(r#"passthrough = ["battery", "battery"]"#),
+            ),
+            (
+                "group",
+                sensor("") + "[[group]]\nid = \"attic\"\nsensors = [\"attic\"]",
+            ),
+        ] {
+            assert!(
+                try_load(&format!("collision_{name}"), &toml).is_err(),
+                "{name}"
+            );
+        }
+        assert!(try_load("collision_none", &sensor(r#"passthrough = ["battery"]"#)).is_ok());
+    }
+
+    #[test]
+    fn unknown_options() {
+        let sensor = r#"
+            [[sensor]]
+            topic = "zigbee2mqtt/attic"
+        "#;
+        let output_broker = r#"
+            [output_broker]
+            broker_addr = "127.0.0.1:1884"
+            client_id = "humidity"
+            username = "humidity"
+            password = ""
+            port = 1884
+        "#;
+        for (name, toml) in [
+            ("top_level", format!("output_topik = \"a\"{sensor}")),
+            ("broker", format!("{output_broker}{sensor}")),
+        ] {
+            let error = try_load(&format!("unknown_{name}"), &toml).err().unwrap();
+            assert!(error.to_string().starts_with("Unknown option"), "{error}");
+        }
+        assert!(try_load("known_options", sensor).is_ok());
+    }
+
+    #[test]
+    fn tunable_offsets() {
+        let sensor = |options: &str| {
+            format!(
+                r#"
+                [[sensor]]
+                id = "attic"
+                topic = "zigbee2mqtt/attic"
+                tunable_offsets = true
+                {options}
+                "#
+            )
+        };
+        assert!(try_load("offset_too_large", &sensor("humidity_offset = -25.5")).is_err());
+
+        let config = load("offsets", &sensor("temperature_offset = 0.25"));
+        let (tx, rx) = mpsc::channel();
+        let subscriptions = handlers(&config.sensors[0], &tx, false, false, None).unwrap();
+        let [(_, temperature), (_, humidity)] = &subscriptions.commands[..] else {
+            panic!("expected temperature and humidity offset commands");
+        };
+        temperature(b"0.125".to_vec());
+        humidity(b"30".to_vec());
+
+        let offsets: Vec<String> = rx
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::Publish(message) => Some(message.payload),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(offsets, ["0.25", "0", "0.125"]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Running as a Windows service, registered with `--install-service` and started by the service
+//! control manager with `--service`
+
+use crate::args::{Args, ServiceCommand};
+use crate::event::Event;
+use crate::failure;
+use log::{error, info};
+use std::error::Error;
+use std::ffi::OsString;
+use std::sync::mpsc;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const NAME: &str = "mqtt_dewpoint";
+
+define_windows_service!(ffi_service_main, service_main);
+
+pub fn handle(command: ServiceCommand, args: &Args) -> Result<(), Box<dyn Error>> {
+    match command {
+        ServiceCommand::Run => service_dispatcher::start(NAME, ffi_service_main)?,
+        ServiceCommand::Install => install(&args.config)?,
+        ServiceCommand::Uninstall => uninstall()?,
+    }
+    Ok(())
+}
+
+fn install(config: &str) -> Result<(), Box<dyn Error>> {
+    let manager =
+        ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)?;
+    // services start in System32, so the config has to be found by its absolute path
+    let config = std::fs::canonicalize(config)?;
+    let info = ServiceInfo {
+        name: OsString::from(NAME),
+        display_name: OsString::from(NAME),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments: vec![config.into_os_string(), OsString::from("--service")],
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    // so recovery actions also apply when it stops with an error, not just when it crashes
+    service.set_failure_actions_on_non_crash_failures(true)?;
+    info!("Installed the {NAME} service");
+    Ok(())
+}
+
+fn uninstall() -> Result<(), Box<dyn Error>> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    manager
+        .open_service(NAME, ServiceAccess::DELETE)?
+        .delete()?;
+    info!("Uninstalled the {NAME} service");
+    Ok(())
+}
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("{e}");
+    }
+}
+
+/// Runs with the arguments the service was installed with, stopping when the service control
+/// manager says to
+fn run_service() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse()?;
+    let (tx, rx) = mpsc::channel();
+    let shutdown = tx.clone();
+    let status = service_control_handler::register(NAME, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = shutdown.send(Event::Shutdown);
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    let set_state = |current_state, controls_accepted, exit_code| {
+        status.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state,
+            controls_accepted,
+            exit_code,
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    set_state(
+        ServiceState::Running,
+        ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        ServiceExitCode::Win32(0),
+    )?;
+    let result = crate::run(args, tx, rx);
+    // the same codes as outside a service, which the service's recovery actions can act on
+    let exit_code = match &result {
+        Ok(()) => ServiceExitCode::Win32(0),
+        Err(e) => ServiceExitCode::ServiceSpecific(failure::exit_code(e.as_ref()).unsigned_abs()),
+    };
+    set_state(
+        ServiceState::Stopped,
+        ServiceControlAccept::empty(),
+        exit_code,
+    )?;
+    result
+}
@@ -0,0 +1,109 @@
+//! Synthetic readings fed through sensors' handlers in place of their source topics, for trying
+//! out discovery and dashboards before any hardware is set up
+
+use crate::config::{Sensor, Source};
+use crate::sensor::Handler;
+use crate::unit::Unit;
+use std::f64::consts::TAU;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Period of the simulated daily cycle, shortened so it shows on a dashboard within the hour
+const PERIOD: Duration = Duration::from_secs(60 * 60);
+
+enum Field {
+    /// JSON with both `temperature` and `humidity`
+    Both,
+    Temperature,
+    Humidity,
+}
+
+struct Simulated {
+    input_unit: Unit,
+    /// Offset into the cycle so sensors don't all read the same
+    phase: f64,
+    handlers: Vec<(Field, Handler)>,
+}
+
+/// Takes the handlers of sensors' source topics out of `sources` and calls them with a new reading
+/// every `interval` from a background thread
+pub fn start(sensors: &[Sensor], sources: &mut Vec<(String, Handler)>, interval: Duration) {
+    let mut simulated = Vec::new();
+    let mut phase = 0.0;
+    for sensor in sensors {
+        let topics = match sensor.source() {
+            Ok(Source::Combined(topic)) => vec![(Field::Both, topic)],
+            Ok(Source::Split {
+                temperature,
+                humidity,
+            }) => vec![
+                (Field::Temperature, temperature),
+                (Field::Humidity, humidity),
+            ],
+            Err(_) => continue,
+        };
+        let handlers = topics
+            .into_iter()
+            .filter_map(|(field, topic)| {
+                let i = sources.iter().position(|(source, _)| source == topic)?;
+                Some((field, sources.remove(i).1))
+            })
+            .collect();
+        simulated.push(Simulated {
+            input_unit: sensor.input_unit,
+            phase,
+            handlers,
+        });
+        phase += 1.0;
+    }
+
+    thread::spawn(move || {
+        let started = Instant::now();
+        let mut noise = Noise::new();
+        loop {
+            let angle = TAU * started.elapsed().as_secs_f64() / PERIOD.as_secs_f64();
+            for sensor in &simulated {
+                let cycle = (angle + sensor.phase).sin();
+                // humidity falls as the temperature rises, like it does indoors over a day
+                let celsius = 3.0_f64.mul_add(cycle, 21.0) + 0.2 * noise.next();
+                let humidity = (-10.0_f64).mul_add(cycle, 50.0) + noise.next();
+                let temperature = sensor.input_unit.convert_celsius(celsius);
+                for (field, handler) in &sensor.handlers {
+                    let payload = match field {
+                        Field::Both => serde_json::json!({
+                            "temperature": temperature,
+                            "humidity": humidity,
+                        })
+                        .to_string(),
+                        Field::Temperature => format!("{temperature:.2}"),
+                        Field::Humidity => format!("{humidity:.1}"),
+                    };
+                    handler(payload.into_bytes());
+                }
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+/// Xorshift generator, which is plenty random for noise on a sine wave
+struct Noise(u64);
+
+impl Noise {
+    fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        Self(u64::from(seed) | 1)
+    }
+
+    /// Uniformly distributed between -1 and 1
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        let high = u32::try_from(self.0 >> 32).unwrap_or_default();
+        f64::from(high) / f64::from(u32::MAX) * 2.0 - 1.0
+    }
+}
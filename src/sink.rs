@@ -0,0 +1,167 @@
+//! Destinations besides MQTT that keep a history of every reading
+
+use crate::api::Api;
+use crate::config::Config;
+use crate::csv_log::CsvLog;
+#[cfg(feature = "influxdb")]
+use crate::influxdb::InfluxDb;
+#[cfg(feature = "postgres")]
+use crate::postgresql::Postgres;
+#[cfg(feature = "sqlite")]
+use crate::sqlite;
+#[cfg(feature = "webhook")]
+use crate::webhook::Webhooks;
+use log::warn;
+use std::error::Error;
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Batches kept to retry while a server is unavailable, after which the oldest items are dropped
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+const MAX_PENDING_BATCHES: usize = 10;
+
+/// Values from one reading of a sensor
+pub struct Record {
+    pub sensor_id: String,
+    pub time: SystemTime,
+    /// Raw and calibrated temperature (°C) and humidity, and each numeric metric (in the sensor's
+    /// `output_unit`), by name
+    pub values: Vec<(String, f64)>,
+}
+
+pub trait Sink: Send {
+    /// Stores a record, logging any failure since a sink being unavailable shouldn't stop
+    /// publishing
+    fn write(&mut self, record: &Record);
+}
+
+/// Every sink in the config
+pub fn open(config: &Config) -> Result<Vec<Box<dyn Sink>>, Box<dyn Error>> {
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &config.sqlite {
+        sinks.push(Box::new(sqlite::Sqlite::open(path)?));
+    }
+    if let Some(csv) = &config.csv {
+        let log = CsvLog::new(csv, &config.sensors)
+            .map_err(|e| format!("{}: {e}", csv.directory.display()))?;
+        sinks.push(Box::new(log));
+    }
+    #[cfg(feature = "influxdb")]
+    if let Some(influxdb) = &config.influxdb {
+        sinks.push(Box::new(InfluxDb::new(influxdb, &config.sensors)));
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(postgres) = &config.postgres {
+        sinks.push(Box::new(Postgres::new(postgres)));
+    }
+    if let Some(addr) = &config.api {
+        sinks.push(Box::new(Api::start(addr, &config.sensors)?));
+    }
+    #[cfg(feature = "webhook")]
+    if let Some(webhooks) = Webhooks::new(&config.sensors) {
+        sinks.push(Box::new(webhooks));
+    }
+    Ok(sinks)
+}
+
+/// Waits until `deadline` for `threads` to finish, e.g. sinks writing their last batches on
+/// shutdown, rather than leaving a stuck server to hold up exiting
+pub fn join(threads: Vec<(&str, JoinHandle<()>)>, deadline: Instant) {
+    for (name, thread) in threads {
+        while !thread.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if thread.is_finished() {
+            let _ = thread.join();
+        } else {
+            warn!("Gave up waiting for {name} to finish");
+        }
+    }
+}
+
+/// Hands items to a background thread that writes them in batches of up to `batch_size`, at
+/// least every `interval`, and once more when this is dropped on shutdown
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+pub struct Batched<T> {
+    items: Option<mpsc::Sender<T>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+impl<T: Send + 'static> Batched<T> {
+    /// `write` is called with each batch, which is retried with the next one if it fails
+    pub fn new<F>(name: &'static str, batch_size: usize, interval: Duration, write: F) -> Self
+    where
+        F: FnMut(&[T]) -> Result<(), Box<dyn Error>> + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            items: Some(tx),
+            writer: Some(thread::spawn(move || {
+                write_batches(name, batch_size, interval, &rx, write);
+            })),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        if let Some(items) = &self.items {
+            let _ = items.send(item);
+        }
+    }
+}
+
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+impl<T> Drop for Batched<T> {
+    fn drop(&mut self) {
+        self.items = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(any(feature = "influxdb", feature = "postgres"))]
+fn write_batches<T>(
+    name: &str,
+    batch_size: usize,
+    interval: Duration,
+    rx: &Receiver<T>,
+    mut write: impl FnMut(&[T]) -> Result<(), Box<dyn Error>>,
+) {
+    let mut pending = Vec::new();
+    let mut flushed = Instant::now();
+    loop {
+        let timeout = interval.saturating_sub(flushed.elapsed());
+        let done = match rx.recv_timeout(timeout) {
+            Ok(item) => {
+                pending.push(item);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => false,
+            Err(RecvTimeoutError::Disconnected) => true,
+        };
+        if !(done || pending.len() >= batch_size || flushed.elapsed() >= interval) {
+            continue;
+        }
+
+        flushed = Instant::now();
+        if !pending.is_empty() {
+            match write(&pending) {
+                Ok(()) => pending.clear(),
+                Err(e) => {
+                    warn!("Error writing {} readings to {name}: {e}", pending.len());
+                    let max = batch_size * MAX_PENDING_BATCHES;
+                    if pending.len() > max {
+                        pending.drain(..pending.len() - max);
+                    }
+                }
+            }
+        }
+        if done {
+            return;
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::args::Export;
+use crate::export::Row;
+use crate::sink::{Record, Sink};
+use crate::timestamp;
+use log::warn;
+use rusqlite::{params, Connection};
+use std::error::Error;
+use std::path::Path;
+
+/// Every reading's values in a local SQLite database, one row per value
+pub struct Sqlite(Connection);
+
+impl Sqlite {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let connection = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+        // lets pruning return freed pages to the filesystem. Setting it only takes effect on an
+        // empty database, so one created without it (by an older version) is rebuilt with VACUUM
+        let auto_vacuum: i64 = connection.query_row("PRAGMA auto_vacuum", [], |row| row.get(0))?;
+        if auto_vacuum == 0 {
+            connection.execute_batch("PRAGMA auto_vacuum = INCREMENTAL; VACUUM;")?;
+        }
+        connection.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS readings (
+                sensor TEXT NOT NULL,
+                metric TEXT NOT NULL,
+                value REAL NOT NULL,
+                -- ISO 8601 in UTC, which sorts chronologically
+                timestamp TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS readings_sensor_timestamp
+                ON readings (sensor, timestamp);",
+        )?;
+        Ok(Self(connection))
+    }
+
+    fn insert(&mut self, record: &Record) -> rusqlite::Result<()> {
+        let timestamp = timestamp::format(record.time);
+        let transaction = self.0.transaction()?;
+        {
+            let mut insert = transaction.prepare_cached(
+                "INSERT INTO readings (sensor, metric, value, timestamp) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (metric, value) in &record.values {
+                insert.execute(params![record.sensor_id, metric, value, timestamp])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+/// Stored values matching `export`'s sensor and time range, oldest first
+pub fn history(path: &Path, export: &Export) -> Result<Vec<Row>, Box<dyn Error>> {
+    let connection = Connection::open(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut select = connection.prepare(
+        "SELECT sensor, metric, value, timestamp FROM readings
+        WHERE (?1 IS NULL OR sensor = ?1)
+            AND (?2 IS NULL OR timestamp >= ?2)
+            AND (?3 IS NULL OR timestamp < ?3)
+        ORDER BY timestamp",
+    )?;
+    let rows = select.query_map(params![export.sensor, export.from, export.to], |row| {
+        Ok(Row {
+            sensor: row.get(0)?,
+            metric: row.get(1)?,
+            value: row.get(2)?,
+            timestamp: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
+
+/// Deletes values from before an ISO 8601 timestamp and frees the space they took, returning how
+/// many were deleted
+pub fn prune(path: &Path, before: &str) -> Result<usize, Box<dyn Error>> {
+    let connection = Connection::open(path)?;
+    let deleted =
+        connection.execute("DELETE FROM readings WHERE timestamp < ?1", params![before])?;
+    if deleted > 0 {
+        connection.execute_batch("PRAGMA incremental_vacuum;")?;
+    }
+    Ok(deleted)
+}
+
+impl Sink for Sqlite {
+    fn write(&mut self, record: &Record) {
+        if let Err(e) = self.insert(record) {
+            warn!("Error writing {} to SQLite: {e}", record.sensor_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::ExportFormat;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// A fresh database file in the temp directory
+    fn database(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mqtt_dewpoint_{name}.db"));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+        }
+        path
+    }
+
+    fn record(days: u64) -> Record {
+        Record {
+            sensor_id: String::from("attic"),
+            time: UNIX_EPOCH + Duration::from_secs(days * 24 * 60 * 60),
+            values: vec![
+                (String::from("temperature"), 21.5),
+                (String::from("dewpoint"), 9.2),
+            ],
+        }
+    }
+
+    fn auto_vacuum(path: &Path) -> i64 {
+        let connection = Connection::open(path).unwrap();
+        connection
+            .query_row("PRAGMA auto_vacuum", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn prune_deletes_older_values() {
+        let path = database("prune");
+        let mut sqlite = Sqlite::open(&path).unwrap();
+        for days in [19_000, 19_001, 19_002] {
+            sqlite.write(&record(days));
+        }
+        drop(sqlite);
+
+        let before = timestamp::format(record(19_001).time);
+        assert_eq!(prune(&path, &before).unwrap(), 2);
+        assert_eq!(prune(&path, &before).unwrap(), 0);
+
+        let export = Export {
+            sensor: None,
+            from: None,
+            to: None,
+            format: ExportFormat::Csv,
+        };
+        let timestamps: Vec<String> = history(&path, &export)
+            .unwrap()
+            .into_iter()
+            .map(|row| row.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            [
+                "2022-01-09T00:00:00Z",
+                "2022-01-09T00:00:00Z",
+                "2022-01-10T00:00:00Z",
+                "2022-01-10T00:00:00Z",
+            ]
+        );
+    }
+
+    #[test]
+    fn incremental_auto_vacuum() {
+        let path = database("auto_vacuum_new");
+        drop(Sqlite::open(&path).unwrap());
+        assert_eq!(auto_vacuum(&path), 2);
+
+        // as created before auto_vacuum was set
+        let path = database("auto_vacuum_old");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE readings (sensor TEXT, metric TEXT, value REAL, timestamp TEXT)",
+            )
+            .unwrap();
+        assert_eq!(auto_vacuum(&path), 0);
+        drop(Sqlite::open(&path).unwrap());
+        assert_eq!(auto_vacuum(&path), 2);
+    }
+}
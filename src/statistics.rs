@@ -0,0 +1,146 @@
+use crate::metric::Metric;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Summary of a metric's recent values, published alongside it
+#[derive(Clone, Copy)]
+pub enum Statistic {
+    Min,
+    Max,
+    Mean,
+}
+
+impl Statistic {
+    pub const ALL: [Self; 3] = [Self::Min, Self::Max, Self::Mean];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Min => "min",
+            Self::Max => "max",
+            Self::Mean => "mean",
+        }
+    }
+}
+
+/// Name used for `{metric}` in topic templates, e.g. `dewpoint_max_24h`
+pub fn name(metric: Metric, statistic: Statistic, hours: u64) -> String {
+    format!("{}_{}_{hours}h", metric.name(), statistic.name())
+}
+
+/// Human readable name, e.g. "Dewpoint max 24h"
+pub fn label(metric: Metric, statistic: Statistic, hours: u64) -> String {
+    format!("{} {} {hours}h", metric.label(), statistic.name())
+}
+
+/// Recent values of a metric and when they were calculated
+#[derive(Default)]
+pub struct Samples(VecDeque<(Instant, f64)>);
+
+impl FromIterator<(Instant, f64)> for Samples {
+    fn from_iter<T: IntoIterator<Item = (Instant, f64)>>(values: T) -> Self {
+        Self(values.into_iter().collect())
+    }
+}
+
+impl Samples {
+    /// Adds a value, forgetting values older than `keep`
+    pub fn push(&mut self, value: f64, keep: Duration) {
+        let now = Instant::now();
+        self.0.push_back((now, value));
+        while self
+            .0
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > keep)
+        {
+            self.0.pop_front();
+        }
+    }
+
+    /// Values and when they were added, oldest first
+    pub fn values(&self) -> impl Iterator<Item = (Instant, f64)> + '_ {
+        self.0.iter().copied()
+    }
+
+    /// Calculates a statistic over the values from the last `window`
+    pub fn calculate(&self, statistic: Statistic, window: Duration) -> f64 {
+        let now = Instant::now();
+        let values = self
+            .0
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= window)
+            .map(|(_, value)| *value);
+        match statistic {
+            Statistic::Min => values.fold(f64::INFINITY, f64::min),
+            Statistic::Max => values.fold(f64::NEG_INFINITY, f64::max),
+            Statistic::Mean => {
+                let (sum, count) =
+                    values.fold((0.0, 0_u32), |(sum, count), value| (sum + value, count + 1));
+                sum / f64::from(count)
+            }
+        }
+    }
+
+    /// Least squares slope of the values, per hour, or `None` without at least two values at
+    /// different times
+    pub fn slope(&self) -> Option<f64> {
+        let &(first, _) = self.0.front()?;
+        let points: Vec<(f64, f64)> = self
+            .0
+            .iter()
+            .map(|(at, value)| (at.duration_since(first).as_secs_f64() / 3600.0, *value))
+            .collect();
+        #[allow(clippy::cast_precision_loss)]
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let (covariance, variance) =
+            points
+                .iter()
+                .fold((0.0, 0.0), |(covariance, variance), (x, y)| {
+                    let dx = x - mean_x;
+                    (dx.mul_add(y - mean_y, covariance), dx.mul_add(dx, variance))
+                });
+        (variance > 0.0).then(|| covariance / variance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(points: &[(u64, f64)]) -> Samples {
+        let start = Instant::now();
+        points
+            .iter()
+            .map(|&(minutes, value)| (start + Duration::from_secs(minutes * 60), value))
+            .collect()
+    }
+
+    #[test]
+    fn slope_per_hour() {
+        let slope = samples(&[(0, 10.0), (30, 10.5), (60, 11.0)])
+            .slope()
+            .unwrap();
+        assert!((slope - 1.0).abs() < 1e-9);
+        let slope = samples(&[(0, 5.0), (15, 4.0)]).slope().unwrap();
+        assert!((slope + 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn slope_is_least_squares() {
+        // the best fit through a noisy rise of 2 per hour
+        let slope = samples(&[(0, 0.0), (60, 3.0), (120, 4.0), (180, 6.0)])
+            .slope()
+            .unwrap();
+        assert!((slope - 1.9).abs() < 1e-9);
+        let flat = samples(&[(0, 0.0), (60, 1.0), (120, 0.0)]).slope().unwrap();
+        assert!(flat.abs() < 1e-9);
+    }
+
+    #[test]
+    fn slope_needs_two_times() {
+        assert_eq!(Samples::default().slope(), None);
+        assert_eq!(samples(&[(0, 1.0)]).slope(), None);
+        assert_eq!(samples(&[(5, 1.0), (5, 2.0)]).slope(), None);
+    }
+}
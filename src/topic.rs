@@ -0,0 +1,35 @@
+//! MQTT topic filters, for options that apply to some topics
+
+/// Whether a topic matches an MQTT topic filter, which may have `+` and `#` wildcards
+pub fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        assert!(matches("a/b", "a/b"));
+        assert!(!matches("a/b", "a/c"));
+        assert!(!matches("a/b", "a/b/c"));
+        assert!(!matches("a/b/c", "a/b"));
+        assert!(matches("a/+/c", "a/b/c"));
+        assert!(!matches("a/+", "a/b/c"));
+        assert!(!matches("a/+", "a"));
+        assert!(matches("a/#", "a/b/c"));
+        // # also matches its parent level
+        assert!(matches("a/#", "a"));
+        assert!(matches("#", "a/b"));
+    }
+}
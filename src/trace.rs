@@ -0,0 +1,74 @@
+//! `--trace-packets` logging of what goes to and comes from the brokers
+//!
+//! The mqtt client builds and parses packets internally, so this logs the messages handed to and
+//! received from it rather than the raw packets on the wire.
+
+use crate::config::Broker;
+use crate::sensor::Handler;
+use log::info;
+use std::fmt::Write;
+
+pub fn connect(broker: &Broker, keep_alive: u16) {
+    info!(
+        target: "packets",
+        "CONNECT {} client_id={:?} username={:?} password={} keep_alive={keep_alive}",
+        broker.broker_addr,
+        broker.client_id,
+        broker.username,
+        if broker.password.is_empty() { "<none>" } else { "<masked>" },
+    );
+}
+
+pub fn subscribe(topic: &str) {
+    info!(target: "packets", topic = topic; "SUBSCRIBE {topic}");
+}
+
+pub fn publish(topic: &str, payload: &str, retain: bool) {
+    info!(
+        target: "packets",
+        topic = topic;
+        "PUBLISH {topic} retain={retain} length={}\n{}",
+        payload.len(),
+        hex_dump(payload.as_bytes()),
+    );
+}
+
+/// Wraps `handler` to log each message it's called with
+pub fn received(topic: String, handler: Handler) -> Handler {
+    Box::new(move |payload| {
+        info!(
+            target: "packets",
+            topic = topic.as_str();
+            "RECEIVED {topic} length={}\n{}",
+            payload.len(),
+            hex_dump(&payload),
+        );
+        handler(payload)
+    })
+}
+
+/// 16 bytes per line as offset, hex, and printable ASCII
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if i > 0 {
+            dump.push('\n');
+        }
+        let _ = write!(dump, "{:08x} ", i * 16);
+        for byte in line {
+            let _ = write!(dump, " {byte:02x}");
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = write!(dump, "{:pad$}  |{ascii}|", "", pad = (16 - line.len()) * 3);
+    }
+    dump
+}
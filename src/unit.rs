@@ -0,0 +1,57 @@
+use serde::Deserialize;
+
+#[derive(Clone, Copy, Default, Deserialize)]
+pub enum Unit {
+    #[serde(rename = "C", alias = "°C")]
+    Celsius,
+    #[serde(rename = "F", alias = "°F")]
+    #[default]
+    Fahrenheit,
+    #[serde(rename = "K")]
+    Kelvin,
+}
+
+impl Unit {
+    pub fn convert_celsius(self, celsius: f64) -> f64 {
+        match self {
+            Self::Celsius => celsius,
+            Self::Fahrenheit => celsius.mul_add(1.8, 32_f64),
+            Self::Kelvin => celsius + 273.15,
+        }
+    }
+
+    /// Converts a temperature in this unit to Celsius
+    pub fn to_celsius(self, value: f64) -> f64 {
+        match self {
+            Self::Celsius => value,
+            Self::Fahrenheit => (value - 32.0) / 1.8,
+            Self::Kelvin => value - 273.15,
+        }
+    }
+
+    /// Converts a difference between two temperatures, rather than an absolute temperature
+    pub fn convert_celsius_delta(self, delta: f64) -> f64 {
+        match self {
+            Self::Celsius | Self::Kelvin => delta,
+            Self::Fahrenheit => delta * 1.8,
+        }
+    }
+
+    /// Home Assistant `unit_of_measurement` for this unit
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Self::Celsius => "°C",
+            Self::Fahrenheit => "°F",
+            Self::Kelvin => "K",
+        }
+    }
+
+    /// Home Assistant `unit_of_measurement` for a rate of change per hour
+    pub const fn rate_symbol(self) -> &'static str {
+        match self {
+            Self::Celsius => "°C/h",
+            Self::Fahrenheit => "°F/h",
+            Self::Kelvin => "K/h",
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::Ventilation;
+use crate::discovery;
+use crate::event::{Message, Readings};
+use log::info;
+
+// (metric, component) of each published value
+const VENTILATE: (&str, &str) = ("ventilate", "binary_sensor");
+const DEWPOINT_DELTA: (&str, &str) = ("dewpoint_delta", "sensor");
+
+/// New state for a ventilation advisor, if both of its sensors have recent readings
+pub fn update(ventilation: &Ventilation, readings: &Readings) -> Vec<Message> {
+    let max_age = ventilation.max_age();
+    let recent = |id: &str| {
+        readings
+            .get(id)
+            .filter(|(_, at)| at.elapsed() <= max_age)
+            .map(|(latest, _)| latest.dewpoint)
+    };
+    let (Some(indoor), Some(outdoor)) = (recent(&ventilation.indoor), recent(&ventilation.outdoor))
+    else {
+        return Vec::new();
+    };
+
+    let delta = ventilation
+        .output_unit
+        .convert_celsius_delta(indoor - outdoor);
+    let ventilate = delta > ventilation.margin;
+
+    info!(
+        "Ventilation {}: dewpoint delta {delta:.2}{}, ventilate {ventilate}",
+        ventilation.id,
+        ventilation.output_unit.symbol()
+    );
+
+    vec![
+        Message {
+            topic: ventilation.state_topic(VENTILATE.0, VENTILATE.1),
+            payload: String::from(if ventilate { "ON" } else { "OFF" }),
+            retain: ventilation.retain(),
+            broker: None,
+        },
+        Message {
+            topic: ventilation.state_topic(DEWPOINT_DELTA.0, DEWPOINT_DELTA.1),
+            payload: format!("{delta:.1}"),
+            retain: ventilation.retain(),
+            broker: None,
+        },
+    ]
+}
+
+/// Topics and payloads of the Home Assistant discovery configs for a ventilation advisor
+pub fn discovery_configs(ventilation: &Ventilation, daemon: Option<&str>) -> Vec<(String, String)> {
+    let mut configs = Vec::new();
+    if let Some(topic) = ventilation.discovery_topic(VENTILATE.0, VENTILATE.1) {
+        let payload = serde_json::json!({
+            "name": format!("{} Ventilate", ventilation.name()),
+            "unique_id": discovery::unique_id(&ventilation.id, VENTILATE.0),
+            "device": discovery::device(&ventilation.id, ventilation.name()),
+            "state_topic": ventilation.state_topic(VENTILATE.0, VENTILATE.1),
+        });
+        configs.push((topic, discovery::payload(payload, daemon)));
+    }
+    if let Some(topic) = ventilation.discovery_topic(DEWPOINT_DELTA.0, DEWPOINT_DELTA.1) {
+        let payload = serde_json::json!({
+            "name": format!("{} Dewpoint delta", ventilation.name()),
+            "unique_id": discovery::unique_id(&ventilation.id, DEWPOINT_DELTA.0),
+            "device": discovery::device(&ventilation.id, ventilation.name()),
+            "state_class": "measurement",
+            "state_topic": ventilation.state_topic(DEWPOINT_DELTA.0, DEWPOINT_DELTA.1),
+            "unit_of_measurement": ventilation.output_unit.symbol(),
+        });
+        configs.push((topic, discovery::payload(payload, daemon)));
+    }
+    configs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Latest;
+    use std::time::{Duration, Instant};
+
+    fn ventilation(margin: f64, output_unit: &str) -> Ventilation {
+        toml::from_str(&format!(
+            r#"
+            id = "basement_fan"
+            indoor = "basement"
+            outdoor = "outside"
+            margin = {margin}
+            output_unit = "{output_unit}"
+            output_topic = "humidity/{{sensor_id}}/{{metric}}"
+            "#
+        ))
+        .unwrap()
+    }
+
+    fn readings(indoor: f64, outdoor: f64, outdoor_age: u64) -> Readings {
+        let reading = |dewpoint, age| {
+            let latest = Latest {
+                temperature: 20.0,
+                humidity: 50.0,
+                dewpoint,
+            };
+            (latest, Instant::now() - Duration::from_secs(age))
+        };
+        Readings::from([
+            (String::from("basement"), reading(indoor, 10)),
+            (String::from("outside"), reading(outdoor, outdoor_age)),
+        ])
+    }
+
+    fn payloads(messages: &[Message]) -> Vec<(&str, &str)> {
+        messages
+            .iter()
+            .map(|message| (message.topic.as_str(), message.payload.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn ventilates_past_margin() {
+        let messages = update(&ventilation(3.0, "C"), &readings(15.0, 10.0, 10));
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/basement_fan/ventilate", "ON"),
+                ("humidity/basement_fan/dewpoint_delta", "5.0"),
+            ]
+        );
+        let messages = update(&ventilation(6.0, "C"), &readings(15.0, 10.0, 10));
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/basement_fan/ventilate", "OFF"),
+                ("humidity/basement_fan/dewpoint_delta", "5.0"),
+            ]
+        );
+        let messages = update(&ventilation(3.0, "C"), &readings(10.0, 15.0, 10));
+        assert_eq!(messages[0].payload, "OFF");
+        assert_eq!(messages[1].payload, "-5.0");
+    }
+
+    #[test]
+    fn margin_in_output_unit() {
+        // a 5°C difference is 9°F
+        let messages = update(&ventilation(8.0, "F"), &readings(15.0, 10.0, 10));
+        assert_eq!(
+            payloads(&messages),
+            [
+                ("humidity/basement_fan/ventilate", "ON"),
+                ("humidity/basement_fan/dewpoint_delta", "9.0"),
+            ]
+        );
+        let messages = update(&ventilation(10.0, "F"), &readings(15.0, 10.0, 10));
+        assert_eq!(messages[0].payload, "OFF");
+    }
+
+    #[test]
+    fn nothing_without_both_readings() {
+        assert!(update(&ventilation(3.0, "C"), &readings(15.0, 10.0, 3600)).is_empty());
+        let mut readings = readings(15.0, 10.0, 10);
+        readings.remove("outside");
+        assert!(update(&ventilation(3.0, "C"), &readings).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::config::{self, Sensor};
+use crate::sink::{Record, Sink};
+use crate::timestamp;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before the first retry, doubling with each one after
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// POSTs each reading of sensors with a `webhook` as JSON, from a background thread so a slow or
+/// unavailable endpoint doesn't hold up publishing
+pub struct Webhooks {
+    /// Sensor ID and body of each request
+    requests: Option<mpsc::Sender<(String, String)>>,
+    sender: Option<JoinHandle<()>>,
+    /// Tags of each sensor with a webhook, by ID
+    tags: HashMap<String, serde_json::Value>,
+}
+
+impl Webhooks {
+    /// `None` if no sensor has a webhook
+    pub fn new(sensors: &[Sensor]) -> Option<Self> {
+        let webhooks: HashMap<String, config::Webhook> = sensors
+            .iter()
+            .filter_map(|sensor| Some((sensor.id().to_string(), sensor.webhook.clone()?)))
+            .collect();
+        if webhooks.is_empty() {
+            return None;
+        }
+        let tags = sensors
+            .iter()
+            .filter(|sensor| webhooks.contains_key(sensor.id()))
+            .map(|sensor| (sensor.id().to_string(), serde_json::json!(sensor.tags)))
+            .collect();
+
+        let (tx, rx) = mpsc::channel::<(String, String)>();
+        let sender = thread::spawn(move || {
+            for (sensor_id, body) in rx {
+                if let Some(webhook) = webhooks.get(&sensor_id) {
+                    post(webhook, &sensor_id, &body);
+                }
+            }
+        });
+        Some(Self {
+            requests: Some(tx),
+            sender: Some(sender),
+            tags,
+        })
+    }
+}
+
+impl Sink for Webhooks {
+    fn write(&mut self, record: &Record) {
+        let (Some(requests), Some(tags)) = (&self.requests, self.tags.get(&record.sensor_id))
+        else {
+            return;
+        };
+        let values: serde_json::Map<_, _> = record
+            .values
+            .iter()
+            .map(|(name, value)| (name.clone(), serde_json::json!(value)))
+            .collect();
+        let body = serde_json::json!({
+            "sensor_id": record.sensor_id,
+            "timestamp": timestamp::format(record.time),
+            "tags": tags,
+            "values": values,
+        });
+        let _ = requests.send((record.sensor_id.clone(), body.to_string()));
+    }
+}
+
+impl Drop for Webhooks {
+    /// Sends any requests still queued before shutting down
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+/// Sends a request, retrying with exponential backoff up to the webhook's `retries` times
+fn post(webhook: &config::Webhook, sensor_id: &str, body: &str) {
+    let mut backoff = BACKOFF;
+    for attempt in 0..=webhook.retries {
+        let mut request = ureq::post(&webhook.url)
+            .timeout(TIMEOUT)
+            .set("Content-Type", "application/json");
+        for (name, value) in &webhook.headers {
+            request = request.set(name, value);
+        }
+        let e = match request.send_string(body) {
+            Ok(_) => return,
+            Err(e) => e,
+        };
+        if attempt == webhook.retries {
+            warn!("Error posting {sensor_id} to its webhook, giving up: {e}");
+        } else {
+            warn!(
+                "Error posting {sensor_id} to its webhook, retrying in {}s: {e}",
+                backoff.as_secs()
+            );
+            thread::sleep(backoff);
+            backoff *= 2;
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Runs the daemon against a Mosquitto container, checking what Home Assistant would see. Needs
+//! Docker, so it's only built with `cargo test --features e2e`.
+#![cfg(unix)]
+
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use std::process::{Child, Command};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use testcontainers_modules::mosquitto::Mosquitto;
+use testcontainers_modules::testcontainers::runners::SyncRunner;
+
+/// Longest wait for any one message
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The daemon, interrupted on drop so a failed test doesn't leave it running
+struct Daemon(Child);
+
+impl Daemon {
+    fn start(config: &str) -> Self {
+        Self(
+            Command::new(env!("CARGO_BIN_EXE_mqtt_dewpoint"))
+                .arg(config)
+                .spawn()
+                .expect("Error starting mqtt_dewpoint"),
+        )
+    }
+
+    /// Shuts the daemon down like Ctrl-C does, returning whether it exited successfully
+    fn interrupt(&mut self) -> bool {
+        let _ = Command::new("kill")
+            .args(["-INT", &self.0.id().to_string()])
+            .status();
+        self.0.wait().is_ok_and(|status| status.success())
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Subscribes to every topic, sending each message received as a topic and payload
+fn subscribe(port: u16) -> (Client, Receiver<(String, String)>) {
+    let (client, mut connection) = Client::new(MqttOptions::new("e2e", "127.0.0.1", port), 100);
+    client.subscribe("#", QoS::AtLeastOnce).unwrap();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            match event {
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                    if tx.send((publish.topic, payload)).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    });
+    (client, rx)
+}
+
+/// Payload of the next message on `topic` that `matches`, skipping any others
+fn wait_for(
+    rx: &Receiver<(String, String)>,
+    topic: &str,
+    matches: impl Fn(&str) -> bool,
+) -> String {
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok((received, payload)) if received == topic && matches(&payload) => return payload,
+            Ok(_) => {}
+            Err(_) => panic!("Timed out waiting for a message on {topic}"),
+        }
+    }
+}
+
+#[test]
+fn discovery_state_and_availability() {
+    let mosquitto = Mosquitto::default().start().unwrap();
+    let port = mosquitto.get_host_port_ipv4(1883).unwrap();
+
+    let config = std::env::temp_dir().join("mqtt_dewpoint_e2e.toml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+            broker_addr = "127.0.0.1:{port}"
+            client_id = "humidity"
+            username = "humidity"
+            password = ""
+            output_topic = "homeassistant/sensor/{{sensor_id}}/{{metric}}/state"
+            discovery_prefix = "homeassistant"
+            availability_topic = "homeassistant/sensor/{{sensor_id}}/availability"
+            daemon_availability_topic = "mqtt_dewpoint/availability"
+
+            [[sensor]]
+            topic = "zigbee2mqtt/attic"
+            stale_after = 2
+            "#
+        ),
+    )
+    .unwrap();
+
+    let (client, rx) = subscribe(port);
+    let mut daemon = Daemon::start(config.to_str().unwrap());
+
+    wait_for(&rx, "mqtt_dewpoint/availability", |p| p == "online");
+    let discovery = wait_for(&rx, "homeassistant/sensor/attic/dewpoint/config", |_| true);
+    let discovery: serde_json::Value = serde_json::from_str(&discovery).unwrap();
+    assert_eq!(
+        discovery["state_topic"],
+        "homeassistant/sensor/attic/dewpoint/state"
+    );
+    assert_eq!(discovery["unique_id"], "mqtt_dewpoint_attic_dewpoint");
+    assert_eq!(discovery["availability_mode"], "all");
+
+    // retained, in case the daemon hasn't subscribed yet
+    client
+        .publish(
+            "zigbee2mqtt/attic",
+            QoS::AtLeastOnce,
+            true,
+            r#"{"temperature":21.5,"humidity":45}"#,
+        )
+        .unwrap();
+    let dewpoint = wait_for(&rx, "homeassistant/sensor/attic/dewpoint/state", |_| true);
+    let dewpoint: f64 = dewpoint.parse().unwrap();
+    assert!((48.0..49.5).contains(&dewpoint), "dewpoint {dewpoint}°F");
+    wait_for(&rx, "homeassistant/sensor/attic/availability", |p| {
+        p == "online"
+    });
+
+    // quiet for longer than stale_after
+    wait_for(&rx, "homeassistant/sensor/attic/availability", |p| {
+        p == "offline"
+    });
+
+    assert!(daemon.interrupt());
+    wait_for(&rx, "mqtt_dewpoint/availability", |p| p == "offline");
+    let _ = std::fs::remove_file(&config);
+}